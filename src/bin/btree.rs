@@ -0,0 +1,325 @@
+use btree::btree::{BTree, BTreeBuilder, MAX_BRANCHING_FACTOR};
+use btree::dump::{DumpOptions, Format};
+use btree::error::Error;
+use btree::node_type::{Key, KeyValuePair};
+use std::env;
+use std::fs::File;
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "usage: btree <command> <path> [args]
+
+Operates on the tree stored in the file at <path> (and the write-ahead-log <path>.wal next to it),
+which is opened with the b parameter recorded in its log.
+
+commands:
+    create [--b <b>]                              create an empty tree, b defaults to 60
+    get <key>                                     print the value of a key
+    put <key> <value>                             insert a key-value pair, replacing the value of an existing key
+    delete <key>                                  delete a key
+    scan [--from <key>] [--to <key>]              print the pairs within [from, to) in key order
+    count                                         print the number of pairs
+    dump [--format <jsonl|csv>] [--base64]        print every pair in key order as JSON Lines (default) or CSV,
+                                                  base64 encoding keys and values with --base64
+    load <file> [--format <jsonl|csv>] [--base64] [--b <b>]
+                                                  insert the pairs of a dump (- for stdin), reporting rejected rows,
+                                                  creating the tree (with b defaulting to 60) if it does not exist
+    stats                                         print the shape of the tree and its space usage
+    check                                         verify the tree invariants and page checksums, reporting every violation
+    print                                         print the structure of the tree";
+
+/// Args holds the positional arguments and the options of a command line.
+struct Args {
+    positional: Vec<String>,
+    b: Option<usize>,
+    from: Option<String>,
+    to: Option<String>,
+    dump_options: DumpOptions,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut res = Args {
+            positional: Vec::new(),
+            b: None,
+            from: None,
+            to: None,
+            dump_options: DumpOptions::default(),
+        };
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--b" => {
+                    let value = args.next().ok_or("--b requires a value")?;
+                    res.b = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid b parameter: {}", value))?,
+                    );
+                }
+                "--from" => res.from = Some(args.next().ok_or("--from requires a value")?),
+                "--to" => res.to = Some(args.next().ok_or("--to requires a value")?),
+                "--format" => {
+                    let value = args.next().ok_or("--format requires a value")?;
+                    res.dump_options.format = match value.as_str() {
+                        "jsonl" => Format::JsonLines,
+                        "csv" => Format::Csv,
+                        _ => return Err(format!("invalid format: {}", value)),
+                    };
+                }
+                "--base64" => res.dump_options.base64 = true,
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ => res.positional.push(arg),
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// DEFAULT_B is the b parameter of the trees created without --b, the largest one supported.
+const DEFAULT_B: usize = MAX_BRANCHING_FACTOR / 2;
+
+/// open opens the existing tree at path with the b parameter recorded in its log,
+/// which must match the given one (if any).
+fn open(path: &str, b: Option<usize>) -> Result<BTree, Error> {
+    let builder = BTreeBuilder::new().path(Path::new(path)).truncate(false);
+    match b {
+        Some(b) => builder.b_parameter(b).build(),
+        None => builder.build(),
+    }
+}
+
+/// create creates an empty tree at path with the given b parameter.
+fn create(path: &str, b: usize) -> Result<BTree, Error> {
+    BTreeBuilder::new()
+        .path(Path::new(path))
+        .b_parameter(b)
+        .build()
+}
+
+fn get(btree: &mut BTree, key: &str) -> Result<bool, Error> {
+    match btree.search(key.to_string()) {
+        Ok(kv) => {
+            println!("{}", kv.value);
+            Ok(true)
+        }
+        Err(Error::KeyNotFound) => {
+            eprintln!("key not found: {}", key);
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// put inserts the pair, replacing the one of an existing key in the same single write.
+fn put(btree: &mut BTree, key: &str, value: &str) -> Result<bool, Error> {
+    btree.insert(KeyValuePair::new(key.to_string(), value.to_string()))?;
+    Ok(true)
+}
+
+fn delete(btree: &mut BTree, key: &str) -> Result<bool, Error> {
+    match btree.delete(Key(key.to_string())) {
+        Ok(()) => Ok(true),
+        Err(Error::KeyNotFound) => {
+            eprintln!("key not found: {}", key);
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn scan(btree: &mut BTree, from: &Option<String>, to: &Option<String>) -> Result<bool, Error> {
+    let start = match from {
+        Some(from) => Bound::Included(from.clone()),
+        None => Bound::Unbounded,
+    };
+    let end = match to {
+        Some(to) => Bound::Excluded(to.clone()),
+        None => Bound::Unbounded,
+    };
+    for kv in btree.range((start, end))? {
+        let kv = kv?;
+        println!("{}\t{}", kv.key, kv.value);
+    }
+    Ok(true)
+}
+
+fn count(btree: &mut BTree) -> Result<bool, Error> {
+    println!("{}", btree.len()?);
+    Ok(true)
+}
+
+fn stats(btree: &mut BTree) -> Result<bool, Error> {
+    let stats = btree.stats()?;
+    println!("height:            {}", stats.height);
+    println!("keys:              {}", stats.keys);
+    println!("internal nodes:    {}", stats.internal_nodes);
+    println!("leaf nodes:        {}", stats.leaf_nodes);
+    for (name, fill) in [
+        ("internal fill:", &stats.internal_fill),
+        ("leaf fill:", &stats.leaf_fill),
+    ] {
+        println!(
+            "{:<18} min {:.1}%, avg {:.1}%, max {:.1}%",
+            name,
+            fill.min * 100.0,
+            fill.average * 100.0,
+            fill.max * 100.0
+        );
+    }
+    println!("file size:         {} bytes", stats.file_size);
+    println!("reachable pages:   {}", stats.reachable_pages);
+    println!("unreachable pages: {}", stats.unreachable_pages);
+    println!("wal size:          {} bytes", stats.wal_size);
+    Ok(true)
+}
+
+fn dump(btree: &mut BTree, options: &DumpOptions) -> Result<bool, Error> {
+    let stdout = io::stdout();
+    btree.dump(stdout.lock(), options)?;
+    Ok(true)
+}
+
+/// load inserts the pairs dumped to a file and returns whether every row was inserted.
+fn load(btree: &mut BTree, file: &str, options: &DumpOptions) -> Result<bool, Error> {
+    let report = match file {
+        "-" => btree.load(io::stdin().lock(), options)?,
+        _ => btree.load(File::open(file)?, options)?,
+    };
+    for rejected in &report.rejected {
+        eprintln!("line {}: {}", rejected.line, rejected.reason);
+    }
+    println!(
+        "{} pair(s) loaded, {} row(s) rejected",
+        report.loaded,
+        report.rejected.len()
+    );
+    Ok(report.rejected.is_empty())
+}
+
+/// check prints every violation found in the tree and returns whether the tree is consistent.
+fn check(btree: &mut BTree) -> Result<bool, Error> {
+    let violations = btree.check()?;
+    for violation in &violations {
+        println!("page {}: {:?}", violation.offset.0, violation.kind);
+    }
+    println!("{} violation(s) found", violations.len());
+    Ok(violations.is_empty())
+}
+
+fn run(args: Args) -> Result<bool, String> {
+    let (command, path, rest) = match args.positional.as_slice() {
+        [command, path, rest @ ..] => (command.as_str(), path.as_str(), rest),
+        _ => return Err(USAGE.to_string()),
+    };
+    let exists = Path::new(path).exists();
+    let btree = match command {
+        "create" if exists => return Err(format!("file exists: {}", path)),
+        // Loading a dump into a missing file creates the tree first.
+        "create" | "load" if !exists => create(path, args.b.unwrap_or(DEFAULT_B)),
+        "load" => open(path, args.b),
+        _ if args.b.is_some() => return Err("--b only applies to create and load".to_string()),
+        _ if !exists => return Err(format!("no such file: {}", path)),
+        _ => open(path, None),
+    };
+    let mut btree = btree.map_err(|e| format!("failed to open tree: {:?}", e))?;
+    let res = match (command, rest) {
+        ("create", []) => Ok(true),
+        ("get", [key]) => get(&mut btree, key),
+        ("put", [key, value]) => put(&mut btree, key, value),
+        ("delete", [key]) => delete(&mut btree, key),
+        ("scan", []) => scan(&mut btree, &args.from, &args.to),
+        ("count", []) => count(&mut btree),
+        ("dump", []) => dump(&mut btree, &args.dump_options),
+        ("load", [file]) => load(&mut btree, file, &args.dump_options),
+        ("stats", []) => stats(&mut btree),
+        ("check", []) => check(&mut btree),
+        ("print", []) => btree.print().map(|_| true),
+        _ => return Err(USAGE.to_string()),
+    };
+    res.map_err(|e| format!("{} failed: {:?}", command, e))
+}
+
+fn main() {
+    let res = Args::parse(env::args().skip(1)).and_then(run);
+    match res {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{run, Args};
+    use std::fs;
+
+    fn run_line(line: &str) -> Result<bool, String> {
+        run(Args::parse(line.split_whitespace().map(str::to_string))?)
+    }
+
+    #[test]
+    fn commands_work() -> Result<(), String> {
+        use btree::btree::BTreeBuilder;
+
+        let dir = std::env::temp_dir().join(format!("btree-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let path = dir.join("db");
+        let path = path.to_str().unwrap();
+
+        assert!(run_line(&format!("put {} a 1", path)).is_err());
+        assert_eq!(run_line(&format!("create {} --b 2", path)), Ok(true));
+        assert!(run_line(&format!("create {} --b 2", path)).is_err());
+        for i in 0..10 {
+            assert_eq!(run_line(&format!("put {} {} {}", path, i, i)), Ok(true));
+        }
+        assert_eq!(run_line(&format!("get {} 3", path)), Ok(true));
+        // Putting an existing key replaces its value.
+        assert_eq!(run_line(&format!("put {} 3 three", path)), Ok(true));
+        assert_eq!(run_line(&format!("delete {} 4", path)), Ok(true));
+        assert_eq!(run_line(&format!("get {} 4", path)), Ok(false));
+        assert_eq!(run_line(&format!("delete {} 4", path)), Ok(false));
+        assert_eq!(run_line(&format!("check {}", path)), Ok(true));
+        // The b parameter is read from the tree rather than given.
+        assert!(run_line(&format!("count {} --b 2", path)).is_err());
+
+        // A second tree in the same directory is created by loading a dump into it.
+        let dump = dir.join("dump.csv");
+        fs::write(&dump, "key,value\na,1\nb,2\n").map_err(|e| e.to_string())?;
+        let dump = dump.to_str().unwrap();
+        let copy = dir.join("db2");
+        let copy = copy.to_str().unwrap();
+        let line = format!("load {} {} --format csv --b 3", copy, dump);
+        assert_eq!(run_line(&line), Ok(true));
+        assert_eq!(run_line(&format!("get {} b", copy)), Ok(true));
+        // Loading into an existing tree checks the given b parameter against its log.
+        let line = format!("load {} {} --format csv --b 2", copy, dump);
+        assert!(run_line(&line).is_err());
+        assert_eq!(run_line(&format!("get {} 3", path)), Ok(true));
+
+        let mut btree = BTreeBuilder::new()
+            .path(path)
+            .truncate(false)
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+        let pairs: Vec<(String, String)> = btree
+            .scan(..)
+            .map_err(|e| format!("{:?}", e))?
+            .into_iter()
+            .map(|kv| (kv.key, kv.value))
+            .collect();
+        let mut expected: Vec<(String, String)> = (0..10)
+            .filter(|&i| i != 4)
+            .map(|i| (i.to_string(), i.to_string()))
+            .collect();
+        expected[3].1 = "three".to_string();
+        assert_eq!(pairs, expected);
+        fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
@@ -1,82 +1,166 @@
 use crate::error::Error;
+use crate::merge::MergeOperator;
 use crate::node::Node;
-use crate::node_type::{Key, KeyValuePair, NodeType, Offset};
+use crate::node_type::{drop_expired, now, Count, Key, KeyValuePair, NodeType, Offset};
 use crate::page::Page;
+use crate::page_layout::PAGE_SIZE;
 use crate::pager::Pager;
+use crate::storage::{FileStorage, LogStorage, PageStorage};
 use crate::wal::Wal;
-use std::cmp;
 use std::convert::TryFrom;
-use std::path::Path;
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
 
 /// B+Tree properties.
-pub const MAX_BRANCHING_FACTOR: usize = 200;
+pub const MAX_BRANCHING_FACTOR: usize = 120;
 pub const NODE_KEYS_LIMIT: usize = MAX_BRANCHING_FACTOR - 1;
 
 /// BTree struct represents an on-disk B+tree.
 /// Each node is persisted in the table file, the leaf nodes contain the values.
 pub struct BTree {
-    pager: Pager,
-    b: usize,
-    wal: Wal,
+    pub(crate) pager: Pager,
+    pub(crate) b: usize,
+    pub(crate) wal: Wal,
+    pub(crate) merge_operator: Option<Box<dyn MergeOperator>>,
 }
 
 /// BtreeBuilder is a Builder for the BTree struct.
 pub struct BTreeBuilder {
     /// Path to the tree file.
-    path: &'static Path,
+    path: PathBuf,
+    /// Whether to discard the content of an existing tree file (and its write-ahead-log).
+    truncate: bool,
     /// The BTree parameter, an inner node contains no more than 2*b-1 keys and no less than b-1 keys
     /// and no more than 2*b children and no less than b children.
     b: usize,
+    /// Storage for the tree nodes and the write-ahead-log, overrides path when set.
+    storage: Option<(Box<dyn PageStorage>, Box<dyn LogStorage>)>,
+    /// Operator combining the values of keys with the operands given to BTree::merge.
+    merge_operator: Option<Box<dyn MergeOperator>>,
 }
 
 impl BTreeBuilder {
     pub fn new() -> BTreeBuilder {
         BTreeBuilder {
-            path: Path::new(""),
+            path: PathBuf::new(),
+            truncate: true,
             b: 0,
+            storage: None,
+            merge_operator: None,
         }
     }
 
-    pub fn path(mut self, path: &'static Path) -> BTreeBuilder {
-        self.path = path;
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> BTreeBuilder {
+        self.path = path.as_ref().to_path_buf();
         self
     }
 
+    /// truncate sets whether an existing tree file is emptied (the default),
+    /// or opened so that the tree it holds is recovered from its write-ahead-log.
+    pub fn truncate(mut self, truncate: bool) -> BTreeBuilder {
+        self.truncate = truncate;
+        self
+    }
+
+    /// b_parameter sets the b parameter of a new tree. An existing tree is opened with the one
+    /// it was built with, which is recorded in its write-ahead-log and which b must match if set.
     pub fn b_parameter(mut self, b: usize) -> BTreeBuilder {
         self.b = b;
         self
     }
 
-    pub fn build(&self) -> Result<BTree, Error> {
-        if self.path.to_string_lossy() == "" {
-            return Err(Error::UnexpectedError);
-        }
-        if self.b == 0 {
+    /// storage sets the backends the tree nodes and the write-ahead-log are kept in
+    /// instead of the files derived from path.
+    pub fn storage(
+        mut self,
+        pages: impl PageStorage + 'static,
+        log: impl LogStorage + 'static,
+    ) -> BTreeBuilder {
+        self.storage = Some((Box::new(pages), Box::new(log)));
+        self
+    }
+
+    /// merge_operator sets the operator BTree::merge combines the value of a key with an operand with.
+    pub fn merge_operator(mut self, operator: impl MergeOperator + 'static) -> BTreeBuilder {
+        self.merge_operator = Some(Box::new(operator));
+        self
+    }
+
+    pub fn build(self) -> Result<BTree, Error> {
+        // A full internal node (2b children along with their counts and 2b-1 keys) must fit in a page.
+        if 2 * self.b > MAX_BRANCHING_FACTOR {
             return Err(Error::UnexpectedError);
         }
 
-        let mut pager = Pager::new(self.path)?;
-        let root = Node::new(NodeType::Leaf(vec![]), true, None);
-        let root_offset = pager.write_page(Page::try_from(&root)?)?;
-        let parent_directory = self.path.parent().unwrap_or_else(|| Path::new("/tmp"));
-        let mut wal = Wal::new(parent_directory.to_path_buf())?;
-        wal.set_root(root_offset)?;
+        let (pages, log) = match self.storage {
+            Some(storage) => storage,
+            None => {
+                if self.path.to_string_lossy() == "" {
+                    return Err(Error::UnexpectedError);
+                }
+                // Each tree file has a write-ahead-log of its own next to it, e.g. db.wal for db.
+                let mut wal_path = self.path.clone().into_os_string();
+                wal_path.push(".wal");
+                let wal_path = PathBuf::from(wal_path);
+                let (pages, log) = match self.truncate {
+                    true => (FileStorage::new(&self.path)?, FileStorage::new(&wal_path)?),
+                    false => (
+                        FileStorage::open(&self.path)?,
+                        FileStorage::open(&wal_path)?,
+                    ),
+                };
+                let pages: Box<dyn PageStorage> = Box::new(pages);
+                let log: Box<dyn LogStorage> = Box::new(log);
+                (pages, log)
+            }
+        };
+
+        let mut pager = Pager::new(pages);
+        let mut wal = Wal::new(log)?;
+        // Pages beyond the empty root of a tree whose creation was interrupted belong to a tree
+        // whose write-ahead-log is missing, which starting over would silently hide.
+        if !wal.has_root()? && pager.size()? > PAGE_SIZE {
+            return Err(Error::RootNotFound);
+        }
+        // Pages are laid out for the b parameter the tree was built with,
+        // so an existing tree is only opened with that one.
+        let b = match (wal.get_b()?, self.b) {
+            // Trees built with a larger b (which was allowed before internal nodes kept counts) do not fit.
+            (Some(b), _) if 2 * b > MAX_BRANCHING_FACTOR => return Err(Error::UnexpectedError),
+            (Some(b), 0) => b,
+            (Some(b), requested) if b != requested => return Err(Error::BParameterMismatch { b }),
+            (Some(b), _) => b,
+            (None, 0) => return Err(Error::UnexpectedError),
+            (None, b) => {
+                wal.set_b(b)?;
+                b
+            }
+        };
+        // Recover the latest root if the storage already holds a tree,
+        // otherwise start with an empty root.
+        if !wal.has_root()? {
+            let root = Node::new(NodeType::Leaf(vec![]), true);
+            let root_offset = pager.write_page(Page::try_from(&root)?)?;
+            pager.sync()?;
+            wal.set_root(root_offset)?;
+        }
 
         Ok(BTree {
             pager,
-            b: self.b,
+            b,
             wal,
+            merge_operator: self.merge_operator,
         })
     }
 }
 
 impl Default for BTreeBuilder {
     // A default BTreeBuilder provides a builder with:
-    // - b parameter set to 200
+    // - b parameter set to the largest one a page can hold (60)
     // - path set to '/tmp/db'.
     fn default() -> Self {
         BTreeBuilder::new()
-            .b_parameter(200)
+            .b_parameter(MAX_BRANCHING_FACTOR / 2)
             .path(Path::new("/tmp/db"))
     }
 }
@@ -85,7 +169,7 @@ impl BTree {
     fn is_node_full(&self, node: &Node) -> Result<bool, Error> {
         match &node.node_type {
             NodeType::Leaf(pairs) => Ok(pairs.len() == (2 * self.b - 1)),
-            NodeType::Internal(_, keys) => Ok(keys.len() == (2 * self.b - 1)),
+            NodeType::Internal(_, keys, _) => Ok(keys.len() == (2 * self.b - 1)),
             NodeType::Unexpected => Err(Error::UnexpectedError),
         }
     }
@@ -94,25 +178,38 @@ impl BTree {
         match &node.node_type {
             // A root cannot really be "underflowing" as it can contain less than b-1 keys / pointers.
             NodeType::Leaf(pairs) => Ok(pairs.len() < self.b - 1 && !node.is_root),
-            NodeType::Internal(_, keys) => Ok(keys.len() < self.b - 1 && !node.is_root),
+            NodeType::Internal(_, keys, _) => Ok(keys.len() < self.b - 1 && !node.is_root),
             NodeType::Unexpected => Err(Error::UnexpectedError),
         }
     }
 
-    /// insert a key value pair possibly splitting nodes along the way.
+    fn is_node_overflow(&self, node: &Node) -> Result<bool, Error> {
+        match &node.node_type {
+            NodeType::Leaf(pairs) => Ok(pairs.len() > 2 * self.b - 1),
+            NodeType::Internal(_, keys, _) => Ok(keys.len() > 2 * self.b - 1),
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// commit publishes a new root by logging it to the write-ahead-log.
+    /// Pages are synced first so that a crash never leaves the log pointing at a partially written tree.
+    pub(crate) fn commit(&mut self, root_offset: Offset) -> Result<(), Error> {
+        self.pager.sync()?;
+        self.wal.set_root(root_offset)
+    }
+
+    /// insert a key value pair possibly splitting nodes along the way,
+    /// replacing the pair of a key which is already in the tree.
     pub fn insert(&mut self, kv: KeyValuePair) -> Result<(), Error> {
         let root_offset = self.wal.get_root()?;
-        let root_page = self.pager.get_page(&root_offset)?;
         let new_root_offset: Offset;
         let mut new_root: Node;
-        let mut root = Node::try_from(root_page)?;
+        let mut root = self.pager.get_node(&root_offset)?;
         if self.is_node_full(&root)? {
             // split the root creating a new root and child nodes along the way.
-            new_root = Node::new(NodeType::Internal(vec![], vec![]), true, None);
+            new_root = Node::new(NodeType::Internal(vec![], vec![], vec![]), true);
             // write the new root to disk to aquire an offset for the new root.
             new_root_offset = self.pager.write_page(Page::try_from(&new_root)?)?;
-            // set the old roots parent to the new root.
-            root.parent_offset = Some(new_root_offset.clone());
             root.is_root = false;
             // split the old root.
             let (median, sibling) = root.split(self.b)?;
@@ -121,8 +218,11 @@ impl BTree {
             // write the newly created sibling to disk.
             let sibling_offset = self.pager.write_page(Page::try_from(&sibling)?)?;
             // update the new root with its children and key.
-            new_root.node_type =
-                NodeType::Internal(vec![old_root_offset, sibling_offset], vec![median]);
+            new_root.node_type = NodeType::Internal(
+                vec![old_root_offset, sibling_offset],
+                vec![median],
+                vec![root.node_type.count(), sibling.node_type.count()],
+            );
             // write the new_root to disk.
             self.pager
                 .write_page_at_offset(Page::try_from(&new_root)?, &new_root_offset)?;
@@ -132,13 +232,23 @@ impl BTree {
         }
         // continue recursively.
         self.insert_non_full(&mut new_root, new_root_offset.clone(), kv)?;
+        // A root left with a single child (after expired pairs were dropped below it) is replaced by the child.
+        let new_root_offset = match &new_root.node_type {
+            NodeType::Internal(children, _, _) if children.len() == 1 => {
+                let mut child = self.pager.get_node(&children[0])?;
+                child.is_root = true;
+                self.pager.write_page(Page::try_from(&child)?)?
+            }
+            _ => new_root_offset,
+        };
         // finish by setting the root to its new copy.
-        self.wal.set_root(new_root_offset)
+        self.commit(new_root_offset)
     }
 
     /// insert_non_full (recursively) finds a node rooted at a given non-full node.
     /// to insert a given key-value pair. Here we assume the node is
     /// already a copy of an existing node in a copy-on-write root to node traversal.
+    /// A pair of the same key (expired or not) is replaced by the given pair.
     fn insert_non_full(
         &mut self,
         node: &mut Node,
@@ -147,24 +257,27 @@ impl BTree {
     ) -> Result<(), Error> {
         match &mut node.node_type {
             NodeType::Leaf(ref mut pairs) => {
-                let idx = pairs.binary_search(&kv).unwrap_or_else(|x| x);
-                pairs.insert(idx, kv);
+                // Rewriting the leaf drops its expired pairs (an expired pair of the same key included).
+                drop_expired(pairs);
+                match pairs.binary_search_by(|pair| pair.key.cmp(&kv.key)) {
+                    Ok(idx) => pairs[idx] = kv,
+                    Err(idx) => pairs.insert(idx, kv),
+                }
                 self.pager
                     .write_page_at_offset(Page::try_from(&*node)?, &node_offset)
             }
-            NodeType::Internal(ref mut children, ref mut keys) => {
+            NodeType::Internal(ref mut children, ref mut keys, ref mut counts) => {
                 let idx = keys
                     .binary_search(&Key(kv.key.clone()))
                     .unwrap_or_else(|x| x);
                 let child_offset = children.get(idx).ok_or(Error::UnexpectedError)?.clone();
-                let child_page = self.pager.get_page(&child_offset)?;
-                let mut child = Node::try_from(child_page)?;
+                let mut child = self.pager.get_node(&child_offset)?;
                 // Copy each branching-node on the root-to-leaf walk.
                 // write_page appends the given page to the db file thus creating a new node.
                 let new_child_offset = self.pager.write_page(Page::try_from(&child)?)?;
                 // Assign copied child at the proper place.
                 children[idx] = new_child_offset.to_owned();
-                if self.is_node_full(&child)? {
+                let (child_idx, child) = if self.is_node_full(&child)? {
                     // split will split the child at b leaving the [0, b-1] keys
                     // while moving the set of [b, 2b-1] keys to the sibling.
                     let (median, mut sibling) = child.split(self.b)?;
@@ -176,203 +289,419 @@ impl BTree {
                     // at the next index.
                     children.insert(idx + 1, sibling_offset.clone());
                     keys.insert(idx, median.clone());
+                    counts[idx] = child.node_type.count();
+                    counts.insert(idx + 1, sibling.node_type.count());
 
-                    // Write the parent page to disk.
-                    self.pager
-                        .write_page_at_offset(Page::try_from(&*node)?, &node_offset)?;
-                    // Continue recursively.
+                    // Continue recursively into the half the pair belongs to.
                     if kv.key <= median.0 {
-                        self.insert_non_full(&mut child, new_child_offset, kv)
+                        self.insert_non_full(&mut child, new_child_offset, kv)?;
+                        (idx, child)
                     } else {
-                        self.insert_non_full(&mut sibling, sibling_offset, kv)
+                        self.insert_non_full(&mut sibling, sibling_offset, kv)?;
+                        (idx + 1, sibling)
                     }
                 } else {
-                    self.pager
-                        .write_page_at_offset(Page::try_from(&*node)?, &node_offset)?;
-                    self.insert_non_full(&mut child, new_child_offset, kv)
+                    self.insert_non_full(&mut child, new_child_offset, kv)?;
+                    (idx, child)
+                };
+                // Recount the child the pair was inserted into, which holds one more pair
+                // unless the pair replaced one of the same key or expired pairs were dropped.
+                counts[child_idx] = child.node_type.count();
+                // A leaf which dropped its expired pairs (or a node which lost a key to a merge below it)
+                // may underflow, in which case it borrows from or merges with a sibling.
+                if self.is_node_underflow(&child)? {
+                    self.rebalance_child(node, child_idx, child)?;
                 }
+                // Write the parent page to disk.
+                self.pager
+                    .write_page_at_offset(Page::try_from(&*node)?, &node_offset)
             }
             NodeType::Unexpected => Err(Error::UnexpectedError),
         }
     }
 
-    /// search searches for a specific key in the BTree.
+    /// roots returns the offsets of the root of every version of the tree, from the oldest
+    /// to the current one. As nodes are never overwritten each of them is still readable.
+    pub fn roots(&mut self) -> Result<Vec<Offset>, Error> {
+        self.wal.get_roots()
+    }
+
+    /// search searches for a specific key in the BTree, an expired pair is not found.
     pub fn search(&mut self, key: String) -> Result<KeyValuePair, Error> {
+        match self.find_leaf(&key, &mut Vec::new())?.node_type {
+            NodeType::Leaf(pairs) => {
+                if let Ok(idx) = pairs.binary_search_by(|pair| pair.key.as_str().cmp(&key)) {
+                    if !pairs[idx].is_expired(now()) {
+                        return Ok(pairs[idx].clone());
+                    }
+                }
+                Err(Error::KeyNotFound)
+            }
+            _ => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// get_many looks up a batch of keys, returning the pair of each key (None for missing keys)
+    /// in the order the keys were given. The keys are sorted and resolved in a single traversal,
+    /// so a node shared by the paths to several keys is read once.
+    pub fn get_many<K: AsRef<str>>(
+        &mut self,
+        keys: &[K],
+    ) -> Result<Vec<Option<KeyValuePair>>, Error> {
+        let mut sorted: Vec<(usize, &str)> = keys.iter().map(AsRef::as_ref).enumerate().collect();
+        sorted.sort_by_key(|(_, key)| *key);
+        let mut res = vec![None; keys.len()];
         let root_offset = self.wal.get_root()?;
-        let root_page = self.pager.get_page(&root_offset)?;
-        let root = Node::try_from(root_page)?;
-        self.search_node(root, &key)
+        self.get_many_sub_tree(&root_offset, &sorted, &mut res)?;
+        Ok(res)
     }
 
-    /// search_node recursively searches a sub tree rooted at node for a key.
-    fn search_node(&mut self, node: Node, search: &str) -> Result<KeyValuePair, Error> {
-        match node.node_type {
-            NodeType::Internal(children, keys) => {
-                let idx = keys
-                    .binary_search(&Key(search.to_string()))
-                    .unwrap_or_else(|x| x);
-                // Retrieve child page from disk and deserialize.
-                let child_offset = children.get(idx).ok_or(Error::UnexpectedError)?;
-                let page = self.pager.get_page(child_offset)?;
-                let child_node = Node::try_from(page)?;
-                self.search_node(child_node, search)
+    /// get_many_sub_tree resolves sorted keys (along with their index in the result)
+    /// within the sub tree rooted at the given offset.
+    fn get_many_sub_tree(
+        &mut self,
+        offset: &Offset,
+        sorted: &[(usize, &str)],
+        res: &mut [Option<KeyValuePair>],
+    ) -> Result<(), Error> {
+        match self.pager.get_node(offset)?.node_type {
+            NodeType::Internal(children, keys, _) => {
+                let mut rest = sorted;
+                for (idx, child_offset) in children.iter().enumerate() {
+                    // The child at idx holds the keys up to its separator and the last child the remaining ones.
+                    let size = match keys.get(idx) {
+                        Some(Key(separator)) => rest.partition_point(|(_, key)| *key <= separator),
+                        None => rest.len(),
+                    };
+                    let (within, after) = rest.split_at(size);
+                    if !within.is_empty() {
+                        self.get_many_sub_tree(child_offset, within, res)?;
+                    }
+                    rest = after;
+                }
+                Ok(())
             }
             NodeType::Leaf(pairs) => {
-                if let Ok(idx) =
-                    pairs.binary_search_by_key(&search.to_string(), |pair| pair.key.clone())
-                {
-                    return Ok(pairs[idx].clone());
+                let now = now();
+                for (idx, key) in sorted {
+                    if let Ok(pair_idx) = pairs.binary_search_by(|pair| pair.key.as_str().cmp(key))
+                    {
+                        res[*idx] = Some(pairs[pair_idx].clone()).filter(|kv| !kv.is_expired(now));
+                    }
                 }
-                Err(Error::KeyNotFound)
+                Ok(())
             }
             NodeType::Unexpected => Err(Error::UnexpectedError),
         }
     }
 
-    /// delete deletes a given key from the tree.
-    pub fn delete(&mut self, key: Key) -> Result<(), Error> {
+    /// find_leaf descends from the root to the leaf which holds (or would hold) a key,
+    /// pushing each internal node on the way along with the index of the child it descended to.
+    pub(crate) fn find_leaf(
+        &mut self,
+        search: &str,
+        path: &mut Vec<(Node, usize)>,
+    ) -> Result<Node, Error> {
         let root_offset = self.wal.get_root()?;
-        let root_page = self.pager.get_page(&root_offset)?;
-        // Shadow the new root and rewrite it.
-        let mut new_root = Node::try_from(root_page)?;
-        let new_root_page = Page::try_from(&new_root)?;
-        let new_root_offset = self.pager.write_page(new_root_page)?;
-        self.delete_key_from_subtree(key, &mut new_root, &new_root_offset)?;
-        self.wal.set_root(new_root_offset)
+        let mut node = self.pager.get_node(&root_offset)?;
+        while let NodeType::Internal(children, keys, _) = &node.node_type {
+            let idx = keys
+                .binary_search(&Key(search.to_string()))
+                .unwrap_or_else(|x| x);
+            // Retrieve child page from disk and deserialize.
+            let child_offset = children.get(idx).ok_or(Error::UnexpectedError)?;
+            let child = self.pager.get_node(child_offset)?;
+            path.push((node, idx));
+            node = child;
+        }
+        Ok(node)
     }
 
-    /// delete key from subtree recursively traverses a tree rooted at a node in certain offset
-    /// until it finds the given key and delete the key-value pair. Here we assume the node is
-    /// already a copy of an existing node in a copy-on-write root to node traversal.
-    fn delete_key_from_subtree(
-        &mut self,
-        key: Key,
-        node: &mut Node,
-        node_offset: &Offset,
-    ) -> Result<(), Error> {
+    /// scan returns every key-value pair whose key lies within the given range, in key order.
+    pub fn scan<R: RangeBounds<String>>(&mut self, range: R) -> Result<Vec<KeyValuePair>, Error> {
+        self.range(range)?.collect()
+    }
+
+    /// delete deletes a given key from the tree, along with the expired pairs of its leaf.
+    /// The root-to-leaf path is kept in memory on the way down, and the nodes along it
+    /// are copied (and rebalanced if they underflow) on the way back up.
+    pub fn delete(&mut self, key: Key) -> Result<(), Error> {
+        // The internal nodes on the path along with the index of the child the search descended to.
+        let mut path = Vec::new();
+        let mut node = self.find_leaf(&key.0, &mut path)?;
         match &mut node.node_type {
-            NodeType::Leaf(ref mut pairs) => {
+            NodeType::Leaf(pairs) => {
+                drop_expired(pairs);
                 let key_idx = pairs
                     .binary_search_by_key(&key, |kv| Key(kv.key.clone()))
                     .map_err(|_| Error::KeyNotFound)?;
                 pairs.remove(key_idx);
-                self.pager
-                    .write_page_at_offset(Page::try_from(&*node)?, node_offset)?;
-                // Check for underflow - if it occures,
-                // we need to merge with a sibling.
-                // this can only occur if node is not the root (as it cannot "underflow").
-                // continue recoursively up the tree.
-                self.borrow_if_needed(node.to_owned(), &key)?;
             }
-            NodeType::Internal(children, keys) => {
-                let node_idx = keys.binary_search(&key).unwrap_or_else(|x| x);
-                // Retrieve child page from disk and deserialize,
-                // copy over the child page and continue recursively.
-                let child_offset = children.get(node_idx).ok_or(Error::UnexpectedError)?;
-                let child_page = self.pager.get_page(child_offset)?;
-                let mut child_node = Node::try_from(child_page)?;
-                // Fix the parent_offset as the child node is a child of a copied parent
-                // in a copy-on-write root to leaf traversal.
-                // This is important for the case of a node underflow which might require a leaf to root traversal.
-                child_node.parent_offset = Some(node_offset.to_owned());
-                let new_child_page = Page::try_from(&child_node)?;
-                let new_child_offset = self.pager.write_page(new_child_page)?;
-                // Assign the new pointer in the parent and continue reccoursively.
-                children[node_idx] = new_child_offset.to_owned();
-                self.pager
-                    .write_page_at_offset(Page::try_from(&*node)?, node_offset)?;
-                return self.delete_key_from_subtree(key, &mut child_node, &new_child_offset);
+            _ => return Err(Error::UnexpectedError),
+        }
+        self.publish_path(path, node)
+    }
+
+    /// compare_and_swap sets the value of a key (inserting the key, or deleting it if new is None)
+    /// only if its current value (None for a missing or expired key) is the expected one,
+    /// and publishes the change with a single root (an existing pair keeps its expiry).
+    /// On a mismatch nothing is written and the current value is returned instead.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&str>,
+        new: Option<&str>,
+    ) -> Result<Result<(), Option<String>>, Error> {
+        let mut path = Vec::new();
+        let mut node = self.find_leaf(key, &mut path)?;
+        let pairs = match &mut node.node_type {
+            NodeType::Leaf(pairs) => pairs,
+            _ => return Err(Error::UnexpectedError),
+        };
+        drop_expired(pairs);
+        let idx = pairs.binary_search_by(|pair| pair.key.as_str().cmp(key));
+        let current = idx.ok().map(|idx| pairs[idx].value.clone());
+        if current.as_deref() != expected {
+            return Ok(Err(current));
+        }
+        match (idx, new) {
+            (Ok(idx), Some(value)) => pairs[idx].value = value.to_string(),
+            (Ok(idx), None) => {
+                pairs.remove(idx);
+            }
+            (Err(idx), Some(value)) => {
+                pairs.insert(idx, KeyValuePair::new(key.to_string(), value.to_string()))
             }
-            NodeType::Unexpected => return Err(Error::UnexpectedError),
+            (Err(_), None) => return Ok(Ok(())),
         }
-        Ok(())
+        self.publish_path(path, node).map(Ok)
     }
 
-    /// borrow_if_needed checks the node for underflow (following a removal of a key),
-    /// if it underflows it is merged with a sibling node, and than called recoursively
-    /// up the tree. Since the downward root-to-leaf traversal was done using the copy-on-write
-    /// technique we are ensured that any merges will only be reflected in the copied parent in the path.
-    fn borrow_if_needed(&mut self, node: Node, key: &Key) -> Result<(), Error> {
-        if self.is_node_underflow(&node)? {
-            // Fetch the sibling from the parent -
-            // This could be quicker if we implement sibling pointers.
-            let parent_offset = node.parent_offset.clone().ok_or(Error::UnexpectedError)?;
-            let parent_page = self.pager.get_page(&parent_offset)?;
-            let mut parent_node = Node::try_from(parent_page)?;
-            // The parent has to be an "internal" node.
-            match parent_node.node_type {
-                NodeType::Internal(ref mut children, ref mut keys) => {
-                    let idx = keys.binary_search(key).unwrap_or_else(|x| x);
-                    // The sibling is in idx +- 1 as the above index led
-                    // the downward search to node.
-                    let sibling_idx;
-                    match idx > 0 {
-                        false => sibling_idx = idx + 1,
-                        true => sibling_idx = idx - 1,
-                    }
+    /// publish_path writes the copy of a modified leaf along with the nodes on the path to it
+    /// (as found by find_leaf) and commits the new root.
+    pub(crate) fn publish_path(
+        &mut self,
+        path: Vec<(Node, usize)>,
+        leaf: Node,
+    ) -> Result<(), Error> {
+        let mut node = leaf;
+        // Write the copy of each node on the path from the bottom up,
+        // if a node underflows it borrows from or merges with a sibling in its parent,
+        // if it overflows it is split in two.
+        for (mut parent, idx) in path.into_iter().rev() {
+            self.rebalance_child(&mut parent, idx, node)?;
+            node = parent;
+        }
+        // An overflowing root is split under a new root.
+        if self.is_node_overflow(&node)? {
+            node.is_root = false;
+            let mut root = Node::new(
+                NodeType::Internal(vec![Offset(0)], vec![], vec![node.node_type.count()]),
+                true,
+            );
+            self.rebalance_child(&mut root, 0, node)?;
+            node = root;
+        }
+        // A root left with a single child is replaced by the child.
+        if let NodeType::Internal(children, _, _) = &node.node_type {
+            if children.len() == 1 {
+                node = self.pager.get_node(&children[0])?;
+                node.is_root = true;
+            }
+        }
+        let new_root_offset = self.pager.write_page(Page::try_from(&node)?)?;
+        self.commit(new_root_offset)
+    }
 
-                    let sibling_offset = children.get(sibling_idx).ok_or(Error::UnexpectedError)?;
-                    let sibling_page = self.pager.get_page(sibling_offset)?;
-                    let sibling = Node::try_from(sibling_page)?;
-                    let merged_node = self.merge(node, sibling)?;
-                    let merged_node_offset =
-                        self.pager.write_page(Page::try_from(&merged_node)?)?;
-                    let merged_node_idx = cmp::min(idx, sibling_idx);
-                    // remove the old nodes.
-                    children.remove(merged_node_idx);
-                    // remove shifts nodes to the left.
-                    children.remove(merged_node_idx);
-                    // if the parent is the root, and there is a single child - the merged node -
-                    // we can safely replace the root with the child.
-                    if parent_node.is_root && children.is_empty() {
-                        self.wal.set_root(merged_node_offset)?;
-                        return Ok(());
-                    }
-                    // remove the keys that separated the two nodes from each other:
-                    keys.remove(idx);
-                    // write the new node in place.
-                    children.insert(merged_node_idx, merged_node_offset);
-                    // write the updated parent back to disk and continue up the tree.
-                    self.pager
-                        .write_page_at_offset(Page::try_from(&parent_node)?, &parent_offset)?;
-                    return self.borrow_if_needed(parent_node, key);
+    /// rebalance_child writes the child at the given index of a parent (a copy of which is being
+    /// modified on the path up the tree) and points the parent at it. If the child underflows
+    /// it borrows a key from a sibling which can spare one, otherwise it is merged with a sibling.
+    /// If the child overflows it is split, adding the sibling and the median key to the parent.
+    fn rebalance_child(
+        &mut self,
+        parent: &mut Node,
+        idx: usize,
+        mut node: Node,
+    ) -> Result<(), Error> {
+        let (children, keys, counts) = match &mut parent.node_type {
+            NodeType::Internal(children, keys, counts) => (children, keys, counts),
+            _ => return Err(Error::UnexpectedError),
+        };
+        if self.is_node_overflow(&node)? {
+            let (median, sibling) = node.split(self.b)?;
+            children[idx] = self.pager.write_page(Page::try_from(&node)?)?;
+            counts[idx] = node.node_type.count();
+            children.insert(idx + 1, self.pager.write_page(Page::try_from(&sibling)?)?);
+            counts.insert(idx + 1, sibling.node_type.count());
+            keys.insert(idx, median);
+            return Ok(());
+        }
+        if !self.is_node_underflow(&node)? {
+            children[idx] = self.pager.write_page(Page::try_from(&node)?)?;
+            counts[idx] = node.node_type.count();
+            return Ok(());
+        }
+        let left = match idx > 0 {
+            true => Some(self.pager.get_node(&children[idx - 1])?),
+            false => None,
+        };
+        let right = match children.get(idx + 1) {
+            Some(offset) => Some(self.pager.get_node(offset)?),
+            None => None,
+        };
+
+        // Borrow from a sibling which has keys to spare, preferring the left one.
+        // A leaf which dropped expired pairs may lack several keys, in which case it keeps
+        // borrowing until it no longer underflows or the sibling cannot spare any more keys.
+        let (sibling_idx, mut sibling) = match (left, right) {
+            (Some(left), _) if self.has_spare_key(&left)? => (idx - 1, left),
+            (_, Some(right)) if self.has_spare_key(&right)? => (idx + 1, right),
+            (Some(left), _) => (idx - 1, left),
+            (None, Some(right)) => (idx + 1, right),
+            (None, None) => return Err(Error::UnexpectedError),
+        };
+        while self.is_node_underflow(&node)? && self.has_spare_key(&sibling)? {
+            match sibling_idx < idx {
+                true => {
+                    keys[idx - 1] =
+                        self.borrow_from_left(&mut sibling, &mut node, &keys[idx - 1])?
                 }
-                _ => return Err(Error::UnexpectedError),
+                false => keys[idx] = self.borrow_from_right(&mut node, &mut sibling, &keys[idx])?,
             }
         }
+        if !self.is_node_underflow(&node)? {
+            children[sibling_idx] = self.pager.write_page(Page::try_from(&sibling)?)?;
+            counts[sibling_idx] = sibling.node_type.count();
+            children[idx] = self.pager.write_page(Page::try_from(&node)?)?;
+            counts[idx] = node.node_type.count();
+            return Ok(());
+        }
+
+        // The sibling cannot spare a key, merge the node with it.
+        let (merged_node_idx, merged_node) = match sibling_idx < idx {
+            true => (idx - 1, self.merge_nodes(sibling, node, &keys[idx - 1])?),
+            false => (idx, self.merge_nodes(node, sibling, &keys[idx])?),
+        };
+        let merged_node_offset = self.pager.write_page(Page::try_from(&merged_node)?)?;
+        // Replace the two nodes with the merged node and remove the key that separated them,
+        // in case of internal nodes it was pulled down into the merged node.
+        children.remove(merged_node_idx + 1);
+        children[merged_node_idx] = merged_node_offset;
+        counts.remove(merged_node_idx + 1);
+        counts[merged_node_idx] = merged_node.node_type.count();
+        keys.remove(merged_node_idx);
         Ok(())
     }
 
-    // merges two *sibling* nodes, it assumes the following:
-    // 1. the two nodes are of the same type.
-    // 2. the two nodes do not accumulate to an overflow,
-    // i.e. |first.keys| + |second.keys| <= [2*(b-1) for keys or 2*b for offsets].
-    fn merge(&self, first: Node, second: Node) -> Result<Node, Error> {
+    /// has_spare_key returns true if a key can be taken from the node without it underflowing.
+    fn has_spare_key(&self, node: &Node) -> Result<bool, Error> {
+        match &node.node_type {
+            NodeType::Leaf(pairs) => Ok(pairs.len() > self.b - 1),
+            NodeType::Internal(_, keys, _) => Ok(keys.len() > self.b - 1),
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// borrow_from_left moves the last entry of the left sibling to the front of the node,
+    /// given the key separating them in their parent it returns the key replacing it.
+    fn borrow_from_left(
+        &self,
+        left: &mut Node,
+        node: &mut Node,
+        separator: &Key,
+    ) -> Result<Key, Error> {
+        match (&mut left.node_type, &mut node.node_type) {
+            (NodeType::Leaf(left_pairs), NodeType::Leaf(pairs)) => {
+                let pair = left_pairs.pop().ok_or(Error::UnexpectedError)?;
+                pairs.insert(0, pair);
+                let last = left_pairs.last().ok_or(Error::UnexpectedError)?;
+                Ok(Key(last.key.clone()))
+            }
+            (
+                NodeType::Internal(left_children, left_keys, left_counts),
+                NodeType::Internal(children, keys, counts),
+            ) => {
+                // The separator comes down to bound the moved child and the last key of the sibling goes up.
+                let child = left_children.pop().ok_or(Error::UnexpectedError)?;
+                children.insert(0, child);
+                let count = left_counts.pop().ok_or(Error::UnexpectedError)?;
+                counts.insert(0, count);
+                keys.insert(0, separator.clone());
+                left_keys.pop().ok_or(Error::UnexpectedError)
+            }
+            _ => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// borrow_from_right moves the first entry of the right sibling to the end of the node,
+    /// given the key separating them in their parent it returns the key replacing it.
+    fn borrow_from_right(
+        &self,
+        node: &mut Node,
+        right: &mut Node,
+        separator: &Key,
+    ) -> Result<Key, Error> {
+        match (&mut node.node_type, &mut right.node_type) {
+            (NodeType::Leaf(pairs), NodeType::Leaf(right_pairs)) => {
+                let pair = right_pairs.remove(0);
+                let key = Key(pair.key.clone());
+                pairs.push(pair);
+                Ok(key)
+            }
+            (
+                NodeType::Internal(children, keys, counts),
+                NodeType::Internal(right_children, right_keys, right_counts),
+            ) => {
+                // The separator comes down to bound the last child of the node
+                // and the first key of the sibling goes up.
+                children.push(right_children.remove(0));
+                counts.push(right_counts.remove(0));
+                keys.push(separator.clone());
+                Ok(right_keys.remove(0))
+            }
+            _ => Err(Error::UnexpectedError),
+        }
+    }
+
+    // merges two *sibling* nodes given the key separating them in their parent,
+    // it assumes the two nodes are of the same type and fails if they do not fit in a single node.
+    // Leaves hold every key of the tree so the separator is simply dropped,
+    // i.e. |first.keys| + |second.keys| <= 2*b-1.
+    // Internal nodes need the separator to bound the last child of the first node,
+    // i.e. |first.keys| + 1 + |second.keys| <= 2*b-1.
+    fn merge_nodes(&self, first: Node, second: Node, separator: &Key) -> Result<Node, Error> {
         match first.node_type {
             NodeType::Leaf(first_pairs) => {
                 if let NodeType::Leaf(second_pairs) = second.node_type {
-                    let merged_pairs: Vec<KeyValuePair> = first_pairs
-                        .into_iter()
-                        .chain(second_pairs.into_iter())
-                        .collect();
+                    if first_pairs.len() + second_pairs.len() > 2 * self.b - 1 {
+                        return Err(Error::UnexpectedError);
+                    }
+                    let merged_pairs: Vec<KeyValuePair> =
+                        first_pairs.into_iter().chain(second_pairs).collect();
                     let node_type = NodeType::Leaf(merged_pairs);
-                    Ok(Node::new(node_type, first.is_root, first.parent_offset))
+                    Ok(Node::new(node_type, first.is_root))
                 } else {
                     Err(Error::UnexpectedError)
                 }
             }
-            NodeType::Internal(first_offsets, first_keys) => {
-                if let NodeType::Internal(second_offsets, second_keys) = second.node_type {
+            NodeType::Internal(first_offsets, first_keys, first_counts) => {
+                if let NodeType::Internal(second_offsets, second_keys, second_counts) =
+                    second.node_type
+                {
+                    if first_keys.len() + 1 + second_keys.len() > 2 * self.b - 1 {
+                        return Err(Error::UnexpectedError);
+                    }
                     let merged_keys: Vec<Key> = first_keys
                         .into_iter()
-                        .chain(second_keys.into_iter())
+                        .chain(std::iter::once(separator.clone()))
+                        .chain(second_keys)
                         .collect();
-                    let merged_offsets: Vec<Offset> = first_offsets
-                        .into_iter()
-                        .chain(second_offsets.into_iter())
-                        .collect();
-                    let node_type = NodeType::Internal(merged_offsets, merged_keys);
-                    Ok(Node::new(node_type, first.is_root, first.parent_offset))
+                    let merged_offsets: Vec<Offset> =
+                        first_offsets.into_iter().chain(second_offsets).collect();
+                    let merged_counts: Vec<Count> =
+                        first_counts.into_iter().chain(second_counts).collect();
+                    let node_type = NodeType::Internal(merged_offsets, merged_keys, merged_counts);
+                    Ok(Node::new(node_type, first.is_root))
                 } else {
                     Err(Error::UnexpectedError)
                 }
@@ -380,37 +709,6 @@ impl BTree {
             NodeType::Unexpected => Err(Error::UnexpectedError),
         }
     }
-
-    /// print_sub_tree is a helper function for recursively printing the nodes rooted at a node given by its offset.
-    fn print_sub_tree(&mut self, prefix: String, offset: Offset) -> Result<(), Error> {
-        println!("{}Node at offset: {}", prefix, offset.0);
-        let curr_prefix = format!("{}|->", prefix);
-        let page = self.pager.get_page(&offset)?;
-        let node = Node::try_from(page)?;
-        match node.node_type {
-            NodeType::Internal(children, keys) => {
-                println!("{}Keys: {:?}", curr_prefix, keys);
-                println!("{}Children: {:?}", curr_prefix, children);
-                let child_prefix = format!("{}   |  ", prefix);
-                for child_offset in children {
-                    self.print_sub_tree(child_prefix.clone(), child_offset)?;
-                }
-                Ok(())
-            }
-            NodeType::Leaf(pairs) => {
-                println!("{}Key value pairs: {:?}", curr_prefix, pairs);
-                Ok(())
-            }
-            NodeType::Unexpected => Err(Error::UnexpectedError),
-        }
-    }
-
-    /// print is a helper for recursively printing the tree.
-    pub fn print(&mut self) -> Result<(), Error> {
-        println!();
-        let root_offset = self.wal.get_root()?;
-        self.print_sub_tree("".to_string(), root_offset)
-    }
 }
 
 #[cfg(test)]
@@ -500,6 +798,298 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn in_memory_storage_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::error::Error;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for (key, value) in [
+            ("a", "shalom"),
+            ("b", "hello"),
+            ("c", "marhaba"),
+            ("d", "olah"),
+        ] {
+            btree.insert(KeyValuePair::new(key.to_string(), value.to_string()))?;
+        }
+
+        let kv = btree.search("c".to_string())?;
+        assert_eq!(kv.key, "c");
+        assert_eq!(kv.value, "marhaba");
+
+        btree.delete(Key("c".to_string()))?;
+        let res = btree.search("c".to_string());
+        assert!(matches!(res, Err(Error::KeyNotFound)));
+        Ok(())
+    }
+
+    #[test]
+    fn trees_in_one_directory_are_independent() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::fs;
+
+        let dir = std::env::temp_dir().join(format!("btree-wal-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let open = |name: &str, truncate: bool| {
+            BTreeBuilder::new()
+                .path(dir.join(name))
+                .truncate(truncate)
+                .b_parameter(2)
+                .build()
+        };
+
+        let mut db = open("db", true)?;
+        db.insert(KeyValuePair::new("a".to_string(), "db".to_string()))?;
+        // A new tree next to an existing one starts empty rather than following its log.
+        let mut other = open("other", false)?;
+        assert!(matches!(
+            other.search("a".to_string()),
+            Err(Error::KeyNotFound)
+        ));
+        for key in ["a", "b", "c", "d", "e"] {
+            other.insert(KeyValuePair::new(key.to_string(), "other".to_string()))?;
+        }
+        drop((db, other));
+
+        let mut db = open("db", false)?;
+        assert_eq!(db.search("a".to_string())?.value, "db");
+        assert!(matches!(
+            db.search("b".to_string()),
+            Err(Error::KeyNotFound)
+        ));
+        let mut other = open("other", false)?;
+        for key in ["a", "b", "c", "d", "e"] {
+            assert_eq!(other.search(key.to_string())?.value, "other");
+        }
+        assert!(other.check()?.is_empty());
+        assert!(dir.join("db.wal").exists() && dir.join("other.wal").exists());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn opening_a_tree_without_its_log_fails() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::fs;
+
+        let dir = std::env::temp_dir().join(format!("btree-no-wal-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let open = |truncate: bool| {
+            BTreeBuilder::new()
+                .path(dir.join("db"))
+                .truncate(truncate)
+                .b_parameter(2)
+                .build()
+        };
+
+        let mut btree = open(true)?;
+        btree.insert(KeyValuePair::new("a".to_string(), "shalom".to_string()))?;
+        drop(btree);
+        // Without its log the pages of the tree cannot be told apart from garbage,
+        // which is reported rather than hidden behind a new empty tree.
+        fs::remove_file(dir.join("db.wal"))?;
+        assert!(matches!(open(false), Err(Error::RootNotFound)));
+        // The tree file is left untouched, so it can be opened once its log is restored.
+        assert!(fs::metadata(dir.join("db"))?.len() > 0);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reopening_with_another_b_fails() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use std::fs;
+
+        let dir = std::env::temp_dir().join(format!("btree-b-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let open = |b: usize, truncate: bool| {
+            BTreeBuilder::new()
+                .path(dir.join("db"))
+                .truncate(truncate)
+                .b_parameter(b)
+                .build()
+        };
+
+        let mut btree = open(2, true)?;
+        btree.insert(KeyValuePair::new("a".to_string(), "shalom".to_string()))?;
+        drop(btree);
+        assert!(matches!(
+            open(3, false),
+            Err(Error::BParameterMismatch { b: 2 })
+        ));
+        assert_eq!(open(2, false)?.search("a".to_string())?.value, "shalom");
+        // Without a b parameter the tree is opened with the one recorded in its log.
+        let mut btree = BTreeBuilder::new()
+            .path(dir.join("db"))
+            .truncate(false)
+            .build()?;
+        assert_eq!(btree.search("a".to_string())?.value, "shalom");
+        // The b parameter heads the log but is not one of the roots.
+        assert_eq!(btree.roots()?.len(), 2);
+        // Truncating the file starts a new tree, which may have another b.
+        let mut btree = open(3, true)?;
+        assert!(btree.scan(..)?.is_empty());
+        drop(btree);
+        // A tree built with a b its pages cannot hold (e.g. the former default of 200) is refused.
+        let mut log = fs::read(dir.join("db.wal"))?;
+        log[..8].copy_from_slice(&200usize.to_be_bytes());
+        fs::write(dir.join("db.wal"), log)?;
+        let res = BTreeBuilder::new()
+            .path(dir.join("db"))
+            .truncate(false)
+            .build();
+        assert!(matches!(res, Err(Error::UnexpectedError)));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn largest_b_fits_in_a_page() -> Result<(), Error> {
+        use crate::btree::{BTreeBuilder, MAX_BRANCHING_FACTOR};
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let b = MAX_BRANCHING_FACTOR / 2;
+        let res = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(b + 1)
+            .build();
+        assert!(matches!(res, Err(Error::UnexpectedError)));
+
+        // Packed leaves and a root with 2*b children, each of them holding the largest keys and values.
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(b)
+            .build()?;
+        let len = (2 * b - 1) * 2 * b;
+        let pairs = (0..len).map(|i| KeyValuePair::new(format!("{:010}", i), format!("{:010}", i)));
+        btree.bulk_load(pairs, 1.0)?;
+        assert!(btree.check()?.is_empty());
+        assert_eq!(btree.len()?, len);
+        Ok(())
+    }
+
+    #[test]
+    fn get_many_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        assert_eq!(btree.get_many(&["a"])?, vec![None]);
+        for i in 0..50 {
+            let key = format!("{:03}", i * 2);
+            btree.insert(KeyValuePair::new(key.clone(), key))?;
+        }
+
+        // Unsorted keys, repeated keys and keys in between or around those of the tree.
+        let keys = ["050", "", "098", "001", "000", "050", "099", "010"];
+        let expected: Vec<Option<KeyValuePair>> = keys
+            .iter()
+            .map(|key| btree.search(key.to_string()).ok())
+            .collect();
+        assert_eq!(btree.get_many(&keys)?, expected);
+        assert_eq!(
+            expected.iter().filter(|kv| kv.is_some()).count(),
+            5,
+            "a mix of present and missing keys"
+        );
+        assert!(btree.get_many::<String>(&[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn compare_and_swap_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for i in 0..20 {
+            let key = format!("{:02}", i);
+            btree.insert(KeyValuePair::new(key.clone(), key))?;
+        }
+        let roots = btree.roots()?.len();
+
+        // Mismatches return the current value and publish nothing.
+        assert_eq!(
+            btree.compare_and_swap("05", Some("x"), Some("y"))?,
+            Err(Some("05".to_string()))
+        );
+        assert_eq!(
+            btree.compare_and_swap("05", None, Some("y"))?,
+            Err(Some("05".to_string()))
+        );
+        assert_eq!(btree.compare_and_swap("50", Some("50"), None)?, Err(None));
+        assert_eq!(btree.compare_and_swap("50", None, None)?, Ok(()));
+        assert_eq!(btree.roots()?.len(), roots);
+
+        // Matches update, insert and delete with a single root each.
+        assert_eq!(btree.compare_and_swap("05", Some("05"), Some("y"))?, Ok(()));
+        assert_eq!(btree.search("05".to_string())?.value, "y");
+        assert_eq!(btree.compare_and_swap("50", None, Some("z"))?, Ok(()));
+        assert_eq!(btree.search("50".to_string())?.value, "z");
+        for i in 0..10 {
+            let key = format!("{:02}", i);
+            let expected = btree.search(key.clone())?.value;
+            assert_eq!(btree.compare_and_swap(&key, Some(&expected), None)?, Ok(()));
+        }
+        assert_eq!(btree.roots()?.len(), roots + 12);
+        assert!(matches!(
+            btree.search("03".to_string()),
+            Err(Error::KeyNotFound)
+        ));
+        assert_eq!(btree.len()?, 11);
+        assert!(btree.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for i in (0..30).rev() {
+            btree.insert(KeyValuePair::new(format!("{:02}", i), i.to_string()))?;
+        }
+
+        let keys = |pairs: Vec<KeyValuePair>| -> Vec<String> {
+            pairs.into_iter().map(|pair| pair.key).collect()
+        };
+        let all: Vec<String> = (0..30).map(|i| format!("{:02}", i)).collect();
+        assert_eq!(keys(btree.scan(..)?), all);
+        assert_eq!(
+            keys(btree.scan("07".to_string().."12".to_string())?),
+            all[7..12].to_vec()
+        );
+        assert_eq!(
+            keys(btree.scan("07".to_string()..="12".to_string())?),
+            all[7..=12].to_vec()
+        );
+        assert_eq!(keys(btree.scan("25".to_string()..)?), all[25..].to_vec());
+        assert!(btree.scan("5".to_string()..)?.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn delete_works() -> Result<(), Error> {
         use crate::btree::BTreeBuilder;
@@ -544,4 +1134,268 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn delete_rebalances_leaves() -> Result<(), Error> {
+        use crate::btree::{BTree, BTreeBuilder};
+        use crate::node_type::{Key, KeyValuePair, NodeType};
+        use crate::print::NodeInfo;
+        use crate::storage::MemoryStorage;
+
+        // new_tree bulk loads leaves of the given size with keys starting at 'a'.
+        fn new_tree(len: u8, fill_factor: f64) -> Result<BTree, Error> {
+            let mut btree = BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(3)
+                .build()?;
+            let pairs = (b'a'..b'a' + len).map(|c| {
+                let key = (c as char).to_string();
+                KeyValuePair::new(key.clone(), key)
+            });
+            btree.bulk_load(pairs, fill_factor)?;
+            Ok(btree)
+        }
+
+        // leaves returns the keys of every leaf from left to right, followed by the keys of the root.
+        fn leaves(btree: &mut BTree) -> Result<Vec<String>, Error> {
+            let mut res = Vec::new();
+            let mut root_keys = String::new();
+            btree.walk(&mut |node: &NodeInfo| -> Result<bool, Error> {
+                match &node.node_type {
+                    NodeType::Leaf(pairs) => {
+                        res.push(pairs.iter().map(|kv| kv.key.as_str()).collect())
+                    }
+                    NodeType::Internal(_, keys, _) if node.depth == 0 => {
+                        root_keys = keys.iter().map(|Key(key)| key.as_str()).collect()
+                    }
+                    _ => {}
+                }
+                Ok(true)
+            })?;
+            res.push(root_keys);
+            Ok(res)
+        }
+
+        // Borrow from the left sibling: [abc][def][ghi] -> [ab][cf][ghi].
+        let mut btree = new_tree(9, 0.6)?;
+        assert_eq!(leaves(&mut btree)?, vec!["abc", "def", "ghi", "cf"]);
+        btree.delete(Key("d".to_string()))?;
+        btree.delete(Key("e".to_string()))?;
+        assert_eq!(leaves(&mut btree)?, vec!["ab", "cf", "ghi", "bf"]);
+        assert!(btree.check()?.is_empty());
+
+        // Borrow from the right sibling: [abc][def][ghi] -> [cd][ef][ghi].
+        let mut btree = new_tree(9, 0.6)?;
+        btree.delete(Key("a".to_string()))?;
+        btree.delete(Key("b".to_string()))?;
+        assert_eq!(leaves(&mut btree)?, vec!["cd", "ef", "ghi", "df"]);
+        assert!(btree.check()?.is_empty());
+
+        // Merge when neither sibling can spare a key: [ab][cd][ef] -> [abd][ef].
+        let mut btree = new_tree(6, 0.4)?;
+        assert_eq!(leaves(&mut btree)?, vec!["ab", "cd", "ef", "bd"]);
+        btree.delete(Key("c".to_string()))?;
+        assert_eq!(leaves(&mut btree)?, vec!["abd", "ef", "d"]);
+        assert!(btree.check()?.is_empty());
+
+        // Merging the last two children of the root replaces the root.
+        btree.delete(Key("e".to_string()))?;
+        assert_eq!(leaves(&mut btree)?, vec!["ab", "df", "b"]);
+        btree.delete(Key("a".to_string()))?;
+        assert_eq!(leaves(&mut btree)?, vec!["bdf", ""]);
+        assert!(btree.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn expired_pairs_are_hidden() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::dump::DumpOptions;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::storage::MemoryStorage;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        use std::collections::BTreeMap;
+
+        for b in 2..5 {
+            let mut rng = StdRng::seed_from_u64(50);
+            let mut btree = BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(b)
+                .build()?;
+            // Pairs expiring a second after the Unix epoch are long expired, unlike those
+            // expiring at the latest time an expiry can hold.
+            let mut live = BTreeMap::new();
+            for i in 0..200 {
+                let key = format!("{:03}", i);
+                let kv = match rng.gen_range(0..3) {
+                    0 => KeyValuePair::with_expiry(key.clone(), key, 1),
+                    1 => KeyValuePair::with_expiry(key.clone(), key, u64::MAX),
+                    _ => KeyValuePair::new(key.clone(), key),
+                };
+                if kv.expires_at != Some(1) {
+                    live.insert(kv.key.clone(), kv.clone());
+                }
+                btree.insert(kv)?;
+            }
+            // Expired pairs are left out of the counts.
+            assert_eq!(btree.len()?, live.len());
+
+            let keys: Vec<String> = (0..200).map(|i| format!("{:03}", i)).collect();
+            let expected: Vec<Option<KeyValuePair>> =
+                keys.iter().map(|key| live.get(key).cloned()).collect();
+            assert_eq!(btree.get_many(&keys)?, expected);
+            for (key, kv) in keys.iter().zip(&expected) {
+                assert_eq!(btree.search(key.clone()).ok(), *kv);
+            }
+            assert_eq!(btree.scan(..)?, live.values().cloned().collect::<Vec<_>>());
+            assert_eq!(btree.first()?, *live.values().next().unwrap());
+            assert_eq!(btree.last()?, *live.values().last().unwrap());
+            let mut cursor = btree.cursor();
+            let mut backward = Vec::new();
+            let mut valid = cursor.seek_to_last()?;
+            while valid {
+                backward.push(cursor.current().unwrap().clone());
+                valid = cursor.prev()?;
+            }
+            assert!(backward.into_iter().rev().eq(live.values().cloned()));
+            assert_eq!(btree.dump(Vec::new(), &DumpOptions::default())?, live.len());
+            let expired = 200 - live.len();
+
+            // Writes treat expired keys as missing and drop the expired pairs of the leaves they rewrite.
+            for i in 0..300 {
+                let key = keys[rng.gen_range(0..keys.len())].clone();
+                match rng.gen_range(0..3) {
+                    0 => match (live.remove(&key), btree.delete(Key(key.clone()))) {
+                        (Some(_), Ok(())) | (None, Err(Error::KeyNotFound)) => {}
+                        (_, res) => panic!("b {} op {}: delete {} {:?}", b, i, key, res),
+                    },
+                    1 if !live.contains_key(&key) => {
+                        let kv = KeyValuePair::new(key.clone(), "new".to_string());
+                        btree.insert(kv.clone())?;
+                        live.insert(key, kv);
+                    }
+                    _ => {
+                        let current = live.get(&key).map(|kv| kv.value.as_str());
+                        assert_eq!(btree.compare_and_swap(&key, current, Some("cas"))?, Ok(()));
+                        // A pair whose value is replaced keeps its expiry.
+                        live.entry(key.clone())
+                            .or_insert_with(|| KeyValuePair::new(key, String::new()))
+                            .value = "cas".to_string();
+                    }
+                }
+                if i % 20 == 0 {
+                    assert_eq!(btree.check()?, vec![], "b {} op {}", b, i);
+                }
+            }
+            assert_eq!(btree.check()?, vec![]);
+            assert_eq!(btree.scan(..)?, live.values().cloned().collect::<Vec<_>>());
+            assert_eq!(btree.len()?, live.len());
+            assert!(btree.stats()?.keys - live.len() < expired);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn writes_drop_expired_pairs_of_the_leaf() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        for b in 2..5 {
+            let mut btree = BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(b)
+                .build()?;
+            // Every other pair expired long ago, bulk loading keeps them in the leaves.
+            let pairs: Vec<KeyValuePair> = (0..100)
+                .map(|i| {
+                    let key = format!("{:03}", i * 2);
+                    match i % 2 {
+                        0 => KeyValuePair::with_expiry(key.clone(), key, 1),
+                        _ => KeyValuePair::new(key.clone(), key),
+                    }
+                })
+                .collect();
+            btree.bulk_load(pairs, 1.0)?;
+            assert_eq!(btree.len()?, 50);
+            assert_eq!(btree.stats()?.keys, 100);
+
+            // Inserting next to an expired pair rewrites its leaf without it.
+            btree.insert(KeyValuePair::new("001".to_string(), "new".to_string()))?;
+            assert_eq!(btree.check()?, vec![]);
+            assert_eq!(btree.len()?, 51);
+            assert_eq!(btree.select(0)?.key, "001");
+            let keys = btree.stats()?.keys;
+            assert!(keys < 101);
+            assert!(matches!(
+                btree.search("000".to_string()),
+                Err(Error::KeyNotFound)
+            ));
+
+            // So does updating the value of a pair through a cursor.
+            let mut cursor = btree.cursor();
+            assert!(cursor.seek("101")?);
+            assert_eq!(cursor.current().unwrap().key, "102");
+            cursor.update_value("updated".to_string())?;
+            assert_eq!(cursor.current().unwrap().value, "updated");
+            assert_eq!(btree.check()?, vec![]);
+            assert_eq!(btree.len()?, 51);
+            assert_eq!(btree.rank("102")?, 26);
+            assert!(btree.stats()?.keys < keys);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn matches_btree_map() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::storage::MemoryStorage;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        use std::collections::BTreeMap;
+
+        for b in 2..5 {
+            for seed in 0..4 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut btree = BTreeBuilder::new()
+                    .storage(MemoryStorage::new(), MemoryStorage::new())
+                    .b_parameter(b)
+                    .build()?;
+                let mut model = BTreeMap::new();
+                for i in 0..1500 {
+                    let key = format!("{:03}", rng.gen_range(0..150));
+                    // Grow the tree for the first half and shrink it for the second one.
+                    let insert_ratio = if i < 750 { 0.7 } else { 0.3 };
+                    if rng.gen_bool(insert_ratio) {
+                        if model.contains_key(&key) {
+                            continue;
+                        }
+                        model.insert(key.clone(), i.to_string());
+                        btree.insert(KeyValuePair::new(key, i.to_string()))?;
+                    } else {
+                        match (model.remove(&key), btree.delete(Key(key))) {
+                            (Some(_), Ok(())) | (None, Err(Error::KeyNotFound)) => {}
+                            (_, res) => panic!("b {} seed {} op {}: delete {:?}", b, seed, i, res),
+                        }
+                    }
+                    if i % 50 == 0 {
+                        assert_eq!(btree.check()?, vec![], "b {} seed {} op {}", b, seed, i);
+                    }
+                }
+
+                assert_eq!(btree.check()?, vec![]);
+                let expected: Vec<KeyValuePair> = model
+                    .iter()
+                    .map(|(key, value)| KeyValuePair::new(key.clone(), value.clone()))
+                    .collect();
+                assert_eq!(btree.scan(..)?, expected);
+                for (key, value) in &model {
+                    assert_eq!(btree.search(key.clone())?.value, *value);
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,1024 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node::Node;
+use crate::node_type::{drop_expired, now, Count, Key, KeyValuePair, NodeType, Offset};
+use crate::page::Page;
+use crate::pager::Pager;
+use std::convert::TryFrom;
+use std::iter::{self, Peekable};
+use std::ops::{Bound, RangeBounds};
+
+/// Entry is a single item of a node being built, a key-value pair in a leaf or a child
+/// along with the largest key of its sub tree and the count of the pairs it holds.
+enum Entry {
+    Pair(KeyValuePair),
+    Child(Offset, Key, Count),
+}
+
+impl Entry {
+    fn key(&self) -> Key {
+        match self {
+            Entry::Pair(kv) => Key(kv.key.clone()),
+            Entry::Child(_, key, _) => key.clone(),
+        }
+    }
+}
+
+/// Level holds the nodes under construction at a single level of the tree.
+#[derive(Default)]
+struct Level {
+    entries: Vec<Entry>,
+    /// The last full node of the level, held back so that it can be rebalanced
+    /// with the final node of the level if the input runs out before it is filled.
+    pending: Option<Vec<Entry>>,
+    /// Number of nodes of the level written so far.
+    written: usize,
+}
+
+/// BulkLoader builds a tree bottom-up: leaves are packed from left to right
+/// and every node written is added to the node being filled one level above it.
+struct BulkLoader<'a> {
+    pager: &'a mut Pager,
+    b: usize,
+    /// Number of keys each node is filled with.
+    target: usize,
+    levels: Vec<Level>,
+}
+
+impl<'a> BulkLoader<'a> {
+    /// capacity returns the number of entries a node at the given depth (leaves at depth zero)
+    /// is filled with before the next node is started.
+    fn capacity(&self, depth: usize) -> usize {
+        match depth {
+            0 => self.target,
+            _ => self.target + 1,
+        }
+    }
+
+    /// min_entries returns the minimal number of entries of a non-root node at the given depth.
+    fn min_entries(&self, depth: usize) -> usize {
+        match depth {
+            0 => self.b - 1,
+            _ => self.b,
+        }
+    }
+
+    fn max_entries(&self, depth: usize) -> usize {
+        match depth {
+            0 => 2 * self.b - 1,
+            _ => 2 * self.b,
+        }
+    }
+
+    fn level(&mut self, depth: usize) -> &mut Level {
+        if self.levels.len() <= depth {
+            self.levels.resize_with(depth + 1, Level::default);
+        }
+        &mut self.levels[depth]
+    }
+
+    /// push adds an entry to the node being filled at the given depth,
+    /// once the node is full it becomes pending and the previously pending node is written.
+    fn push(&mut self, depth: usize, entry: Entry) -> Result<(), Error> {
+        let capacity = self.capacity(depth);
+        let level = self.level(depth);
+        level.entries.push(entry);
+        if level.entries.len() < capacity {
+            return Ok(());
+        }
+        let full = level.entries.drain(..).collect();
+        if let Some(entries) = level.pending.replace(full) {
+            self.write(depth, entries, false)?;
+        }
+        Ok(())
+    }
+
+    /// write writes a node at the given depth, and unless it is the root adds it to its parent.
+    fn write(&mut self, depth: usize, entries: Vec<Entry>, is_root: bool) -> Result<Offset, Error> {
+        let last_key = entries.last().map(Entry::key);
+        let node_type = match depth {
+            0 => NodeType::Leaf(
+                entries
+                    .into_iter()
+                    .map(|entry| match entry {
+                        Entry::Pair(kv) => Ok(kv),
+                        Entry::Child(..) => Err(Error::UnexpectedError),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => {
+                let mut children = Vec::with_capacity(entries.len());
+                let mut keys = Vec::with_capacity(entries.len());
+                let mut counts = Vec::with_capacity(entries.len());
+                for entry in entries {
+                    match entry {
+                        Entry::Child(child_offset, key, count) => {
+                            children.push(child_offset);
+                            keys.push(key);
+                            counts.push(count);
+                        }
+                        Entry::Pair(_) => return Err(Error::UnexpectedError),
+                    }
+                }
+                // The largest key of the last child is bounded by the separator above the node.
+                keys.pop();
+                NodeType::Internal(children, keys, counts)
+            }
+        };
+        let count = node_type.count();
+        let node = Node::new(node_type, is_root);
+        let offset = self.pager.write_page(Page::try_from(&node)?)?;
+        self.level(depth).written += 1;
+        if !is_root {
+            let key = last_key.ok_or(Error::UnexpectedError)?;
+            self.push(depth + 1, Entry::Child(offset.clone(), key, count))?;
+        }
+        Ok(offset)
+    }
+
+    /// finish writes the nodes still under construction level by level
+    /// and returns the offset of the root.
+    fn finish(mut self) -> Result<Offset, Error> {
+        let mut depth = 0;
+        loop {
+            let level = self.level(depth);
+            let last: Vec<Entry> = level.entries.drain(..).collect();
+            let nodes = match level.pending.take() {
+                None => vec![last],
+                Some(pending) if last.is_empty() => vec![pending],
+                Some(pending) if last.len() >= self.min_entries(depth) => vec![pending, last],
+                // The last node is too small to stand on its own,
+                // either merge it into the pending node or share the entries evenly between both.
+                Some(mut entries) => {
+                    entries.extend(last);
+                    match entries.len() <= self.max_entries(depth) {
+                        true => vec![entries],
+                        false => {
+                            let right = entries.split_off(entries.len() / 2);
+                            vec![entries, right]
+                        }
+                    }
+                }
+            };
+            if self.level(depth).written == 0 && nodes.len() == 1 {
+                let entries = nodes.into_iter().next().ok_or(Error::UnexpectedError)?;
+                return self.write(depth, entries, true);
+            }
+            for entries in nodes {
+                self.write(depth, entries, false)?;
+            }
+            depth += 1;
+        }
+    }
+}
+
+impl BTree {
+    /// bulk_load builds the tree bottom-up from pairs sorted by key, filling each node
+    /// to the given fraction (between 0 and 1) of its capacity, and publishes it with a single root.
+    /// The tree must be empty, and loading fails on keys which are out of order or duplicate.
+    pub fn bulk_load<I: IntoIterator<Item = KeyValuePair>>(
+        &mut self,
+        pairs: I,
+        fill_factor: f64,
+    ) -> Result<(), Error> {
+        let root_offset = self.wal.get_root()?;
+        match self.pager.get_node(&root_offset)?.node_type {
+            NodeType::Leaf(pairs) if pairs.is_empty() => {}
+            _ => return Err(Error::TreeNotEmpty),
+        }
+
+        let max_keys = 2 * self.b - 1;
+        let target = (fill_factor * max_keys as f64).round() as usize;
+        let mut loader = BulkLoader {
+            pager: &mut self.pager,
+            b: self.b,
+            target: target.clamp((self.b - 1).max(1), max_keys),
+            levels: Vec::new(),
+        };
+        let mut previous_key: Option<String> = None;
+        for kv in pairs {
+            match &previous_key {
+                Some(previous) if kv.key < *previous => return Err(Error::KeyOutOfOrder),
+                Some(previous) if kv.key == *previous => return Err(Error::KeyAlreadyExists),
+                _ => previous_key = Some(kv.key.clone()),
+            }
+            loader.push(0, Entry::Pair(kv))?;
+        }
+        let root_offset = loader.finish()?;
+        self.commit(root_offset)
+    }
+}
+
+/// BatchOp is a single change applied to a tree by merge_batch.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BatchOp {
+    /// Insert a pair, replacing the value of an existing key.
+    Put(KeyValuePair),
+    /// Delete a key, keys which are not in the tree are ignored.
+    Delete(Key),
+}
+
+impl BatchOp {
+    fn key(&self) -> &str {
+        match self {
+            BatchOp::Put(kv) => &kv.key,
+            BatchOp::Delete(Key(key)) => key,
+        }
+    }
+}
+
+/// Batch yields the operations of a batch in order, failing on keys which are out of order or duplicate.
+struct Batch<I: Iterator<Item = BatchOp>> {
+    ops: Peekable<I>,
+    previous_key: Option<String>,
+}
+
+impl<I: Iterator<Item = BatchOp>> Batch<I> {
+    /// has_next_within returns true if the next operation applies to a key no larger than upper.
+    fn has_next_within(&mut self, upper: Option<&Key>) -> bool {
+        match (self.ops.peek(), upper) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(op), Some(Key(upper))) => op.key() <= upper.as_str(),
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<BatchOp>, Error> {
+        let op = match self.ops.next() {
+            Some(op) => op,
+            None => return Ok(None),
+        };
+        match &self.previous_key {
+            Some(previous) if op.key() < previous.as_str() => return Err(Error::KeyOutOfOrder),
+            Some(previous) if op.key() == previous.as_str() => return Err(Error::KeyAlreadyExists),
+            _ => self.previous_key = Some(op.key().to_string()),
+        }
+        Ok(Some(op))
+    }
+}
+
+/// KeyRange is the range of keys deleted by delete_range.
+struct KeyRange {
+    start: Bound<String>,
+    end: Bound<String>,
+}
+
+impl KeyRange {
+    fn contains(&self, key: &str) -> bool {
+        let after_start = match &self.start {
+            Bound::Included(start) => key >= start.as_str(),
+            Bound::Excluded(start) => key > start.as_str(),
+            Bound::Unbounded => true,
+        };
+        let before_end = match &self.end {
+            Bound::Included(end) => key <= end.as_str(),
+            Bound::Excluded(end) => key < end.as_str(),
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    /// covers returns true if every key within (lower, upper] lies within the range,
+    /// where a missing bound stands for no bound.
+    fn covers(&self, lower: Option<&Key>, upper: Option<&Key>) -> bool {
+        let after_start = match (&self.start, lower) {
+            (Bound::Unbounded, _) => true,
+            (Bound::Included(start) | Bound::Excluded(start), Some(Key(lower))) => lower >= start,
+            (_, None) => false,
+        };
+        let before_end = match (&self.end, upper) {
+            (Bound::Unbounded, _) => true,
+            (Bound::Included(end), Some(Key(upper))) => upper <= end,
+            (Bound::Excluded(end), Some(Key(upper))) => upper < end,
+            (_, None) => false,
+        };
+        after_start && before_end
+    }
+
+    /// misses returns true if no key within (lower, upper] lies within the range.
+    fn misses(&self, lower: Option<&Key>, upper: Option<&Key>) -> bool {
+        let before_start = match (&self.start, upper) {
+            (Bound::Included(start), Some(Key(upper))) => upper < start,
+            (Bound::Excluded(start), Some(Key(upper))) => upper <= start,
+            _ => false,
+        };
+        let after_end = match (&self.end, lower) {
+            (Bound::Included(end) | Bound::Excluded(end), Some(Key(lower))) => lower >= end,
+            _ => false,
+        };
+        before_start || after_end
+    }
+}
+
+/// Deletion is the set of pairs deleted in a single pass by delete_range or sweep_expired.
+enum Deletion {
+    /// The pairs whose key lies within the range, only those live at the given time being counted.
+    Range(KeyRange, u64),
+    /// The pairs which expired by the given time.
+    Expired(u64),
+}
+
+impl Deletion {
+    fn contains(&self, kv: &KeyValuePair) -> bool {
+        match self {
+            Deletion::Range(range, _) => range.contains(&kv.key),
+            Deletion::Expired(now) => kv.is_expired(*now),
+        }
+    }
+
+    /// now returns the time the deletion tells expired pairs by.
+    fn now(&self) -> u64 {
+        match self {
+            Deletion::Range(_, now) | Deletion::Expired(now) => *now,
+        }
+    }
+
+    /// counts returns true if deleting the pair adds to the number of pairs deleted,
+    /// which leaves out the expired pairs within a range as they were hidden already.
+    fn counts(&self, kv: &KeyValuePair) -> bool {
+        match self {
+            Deletion::Range(_, now) => !kv.is_expired(*now),
+            Deletion::Expired(_) => true,
+        }
+    }
+
+    /// covers returns true if every pair of a sub tree holding the keys within (lower, upper] is deleted.
+    fn covers(&self, lower: Option<&Key>, upper: Option<&Key>) -> bool {
+        match self {
+            Deletion::Range(range, _) => range.covers(lower, upper),
+            Deletion::Expired(_) => false,
+        }
+    }
+
+    /// misses returns true if no pair of a sub tree holding the keys within (lower, upper] is deleted.
+    fn misses(&self, lower: Option<&Key>, upper: Option<&Key>) -> bool {
+        match self {
+            Deletion::Range(range, _) => range.misses(lower, upper),
+            Deletion::Expired(_) => false,
+        }
+    }
+}
+
+/// Child is an entry of an internal node being rewritten by merge_batch or a deletion,
+/// either a sub tree left untouched (along with the count of the pairs it holds)
+/// or a node which is yet to be written.
+enum Child {
+    Kept(Offset, Count),
+    New(NodeType),
+}
+
+/// Sub tree of a node being rewritten, along with the largest key it may hold (None for no bound).
+struct Part {
+    child: Child,
+    upper: Option<Key>,
+}
+
+impl BTree {
+    /// merge_batch applies a batch of operations sorted by key to the tree, walking the tree
+    /// and the batch together so that every affected leaf is rewritten once,
+    /// and publishes the result with a single root.
+    /// Keys which are out of order or duplicate fail the merge without publishing anything.
+    pub fn merge_batch<I: IntoIterator<Item = BatchOp>>(&mut self, ops: I) -> Result<(), Error> {
+        let mut batch = Batch {
+            ops: ops.into_iter().peekable(),
+            previous_key: None,
+        };
+        let root_offset = self.wal.get_root()?;
+        match self.merge_sub_tree(root_offset, None, &mut batch)? {
+            Some(parts) => self.publish(parts),
+            None => Ok(()),
+        }
+    }
+
+    /// delete_range deletes every key within the given range in a single copy-on-write pass,
+    /// dropping the sub trees which lie entirely within the range without rewriting them,
+    /// and returns the number of pairs deleted (leaving out the expired pairs dropped along with them).
+    pub fn delete_range<R: RangeBounds<String>>(&mut self, range: R) -> Result<usize, Error> {
+        let range = KeyRange {
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+        };
+        self.delete_all(&Deletion::Range(range, now()))
+    }
+
+    /// sweep_expired deletes every expired pair in a single copy-on-write pass, rewriting only
+    /// the leaves which hold some, and returns the number of pairs deleted.
+    pub fn sweep_expired(&mut self) -> Result<usize, Error> {
+        self.delete_all(&Deletion::Expired(now()))
+    }
+
+    /// delete_all deletes the pairs of the deletion and publishes the result with a single root.
+    fn delete_all(&mut self, deletion: &Deletion) -> Result<usize, Error> {
+        let root_offset = self.wal.get_root()?;
+        let mut deleted = 0;
+        if let Some(parts) =
+            self.delete_sub_tree(root_offset, None, None, deletion, &mut deleted)?
+        {
+            self.publish(parts)?;
+        }
+        Ok(deleted)
+    }
+
+    /// publish writes the parts replacing the root as a single (new) root and commits it.
+    fn publish(&mut self, parts: Vec<Part>) -> Result<(), Error> {
+        let mut parts = parts;
+        while parts.len() > 1 {
+            parts = self.build_parts(parts, None)?;
+        }
+        let mut root = match parts.pop() {
+            Some(Part {
+                child: Child::New(node_type),
+                ..
+            }) => Node::new(node_type, true),
+            Some(Part {
+                child: Child::Kept(..),
+                ..
+            }) => return Err(Error::UnexpectedError),
+            None => Node::new(NodeType::Leaf(vec![]), true),
+        };
+        // A root left with a single child is replaced by the child.
+        while let NodeType::Internal(children, _, _) = &root.node_type {
+            if children.len() != 1 {
+                break;
+            }
+            root = self.pager.get_node(&children[0])?;
+            root.is_root = true;
+        }
+        let root_offset = self.pager.write_page(Page::try_from(&root)?)?;
+        self.commit(root_offset)
+    }
+
+    /// merge_sub_tree applies the operations of the batch on keys no larger than upper
+    /// to the sub tree rooted at the given offset. It returns the (unwritten) nodes replacing it,
+    /// none if the sub tree was emptied or None if it was left untouched.
+    fn merge_sub_tree<I: Iterator<Item = BatchOp>>(
+        &mut self,
+        offset: Offset,
+        upper: Option<&Key>,
+        batch: &mut Batch<I>,
+    ) -> Result<Option<Vec<Part>>, Error> {
+        let node = self.pager.get_node(&offset)?;
+        match node.node_type {
+            NodeType::Leaf(pairs) => {
+                let mut res = Vec::with_capacity(pairs.len());
+                let mut pairs = pairs.into_iter().peekable();
+                let mut changed = false;
+                while batch.has_next_within(upper) {
+                    let op = batch.next()?.ok_or(Error::UnexpectedError)?;
+                    while let Some(kv) = pairs.next_if(|kv| kv.key.as_str() < op.key()) {
+                        res.push(kv);
+                    }
+                    let existing = pairs.next_if(|kv| kv.key == op.key());
+                    match op {
+                        BatchOp::Put(kv) => {
+                            changed |= existing.as_ref() != Some(&kv);
+                            res.push(kv);
+                        }
+                        BatchOp::Delete(_) => changed |= existing.is_some(),
+                    }
+                }
+                if !changed {
+                    return Ok(None);
+                }
+                res.extend(pairs);
+                // The leaf is rewritten anyway, so its expired pairs are dropped as well.
+                drop_expired(&mut res);
+                Ok(Some(self.split_leaf(res, upper)))
+            }
+            NodeType::Internal(children, keys, counts) => {
+                let mut parts = Vec::with_capacity(children.len());
+                let mut changed = false;
+                for (idx, (child_offset, count)) in children.into_iter().zip(counts).enumerate() {
+                    let child_upper = keys.get(idx).or(upper);
+                    let merged = match batch.has_next_within(child_upper) {
+                        true => self.merge_sub_tree(child_offset.clone(), child_upper, batch)?,
+                        false => None,
+                    };
+                    match merged {
+                        Some(merged) => {
+                            changed = true;
+                            parts.extend(merged);
+                        }
+                        None => parts.push(Part {
+                            child: Child::Kept(child_offset, count),
+                            upper: child_upper.cloned(),
+                        }),
+                    }
+                }
+                if !changed {
+                    return Ok(None);
+                }
+                self.fix_underflows(&mut parts)?;
+                Ok(Some(self.build_parts(parts, upper.cloned())?))
+            }
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// delete_sub_tree deletes the pairs of the deletion from the sub tree rooted at
+    /// the given offset, which holds the keys within (lower, upper]. It returns the (unwritten)
+    /// nodes replacing it, none if the sub tree was emptied or None if it was left untouched.
+    fn delete_sub_tree(
+        &mut self,
+        offset: Offset,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+        deletion: &Deletion,
+        deleted: &mut usize,
+    ) -> Result<Option<Vec<Part>>, Error> {
+        let node = self.pager.get_node(&offset)?;
+        match node.node_type {
+            NodeType::Leaf(pairs) => {
+                let count = pairs.len();
+                let mut res = Vec::with_capacity(count);
+                for kv in pairs {
+                    match deletion.contains(&kv) {
+                        true => *deleted += usize::from(deletion.counts(&kv)),
+                        false => res.push(kv),
+                    }
+                }
+                if res.len() == count {
+                    return Ok(None);
+                }
+                // The leaf is rewritten anyway, so its expired pairs are dropped as well.
+                drop_expired(&mut res);
+                Ok(Some(self.split_leaf(res, upper)))
+            }
+            NodeType::Internal(children, keys, counts) => {
+                let mut parts = Vec::with_capacity(children.len());
+                let mut changed = false;
+                for (idx, (child_offset, count)) in children.into_iter().zip(counts).enumerate() {
+                    let child_lower = match idx {
+                        0 => lower,
+                        _ => keys.get(idx - 1),
+                    };
+                    let child_upper = keys.get(idx).or(upper);
+                    if deletion.covers(child_lower, child_upper) {
+                        *deleted += self.live(&child_offset, count, deletion.now())?;
+                        changed = true;
+                        continue;
+                    }
+                    let merged = match deletion.misses(child_lower, child_upper) {
+                        true => None,
+                        false => self.delete_sub_tree(
+                            child_offset.clone(),
+                            child_lower,
+                            child_upper,
+                            deletion,
+                            deleted,
+                        )?,
+                    };
+                    match merged {
+                        Some(merged) => {
+                            changed = true;
+                            parts.extend(merged);
+                        }
+                        None => parts.push(Part {
+                            child: Child::Kept(child_offset, count),
+                            upper: child_upper.cloned(),
+                        }),
+                    }
+                }
+                if !changed {
+                    return Ok(None);
+                }
+                self.fix_underflows(&mut parts)?;
+                Ok(Some(self.build_parts(parts, upper.cloned())?))
+            }
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// split_leaf splits sorted pairs evenly into as few leaves as can hold them.
+    fn split_leaf(&self, pairs: Vec<KeyValuePair>, upper: Option<&Key>) -> Vec<Part> {
+        let max = 2 * self.b - 1;
+        let count = pairs.len().div_ceil(max);
+        let mut res = Vec::with_capacity(count);
+        let mut pairs = pairs.into_iter();
+        for idx in 0..count {
+            let size = share(idx, count, pairs.len());
+            let chunk: Vec<KeyValuePair> = pairs.by_ref().take(size).collect();
+            let upper = match idx + 1 == count {
+                true => upper.cloned(),
+                false => chunk.last().map(|kv| Key(kv.key.clone())),
+            };
+            res.push(Part {
+                child: Child::New(NodeType::Leaf(chunk)),
+                upper,
+            });
+        }
+        res
+    }
+
+    /// fix_underflows merges every new node holding less than the minimal number of entries
+    /// with one of its siblings, sharing the entries evenly between both if they do not fit in one node.
+    fn fix_underflows(&mut self, parts: &mut Vec<Part>) -> Result<(), Error> {
+        let mut idx = 0;
+        while idx < parts.len() && parts.len() > 1 {
+            let underflow = match &parts[idx].child {
+                Child::Kept(..) => false,
+                Child::New(node_type) => self.is_underflow(node_type)?,
+            };
+            if !underflow {
+                idx += 1;
+                continue;
+            }
+            let left_idx = match idx + 1 < parts.len() {
+                true => idx,
+                false => idx - 1,
+            };
+            let right = parts.remove(left_idx + 1);
+            let left = parts.remove(left_idx);
+            let merged = self.merge_parts(left, right)?;
+            for (offset, part) in merged.into_iter().enumerate() {
+                parts.insert(left_idx + offset, part);
+            }
+            idx = left_idx;
+        }
+        Ok(())
+    }
+
+    /// is_underflow returns whether a (non root) node holds less than the minimal number of entries.
+    fn is_underflow(&self, node_type: &NodeType) -> Result<bool, Error> {
+        match node_type {
+            NodeType::Leaf(pairs) => Ok(pairs.len() < self.b - 1),
+            NodeType::Internal(children, _, _) => Ok(children.len() < self.b),
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// merge_parts merges two adjacent sub trees into one node, or two if they do not fit in one.
+    fn merge_parts(&mut self, left: Part, right: Part) -> Result<Vec<Part>, Error> {
+        let separator = left.upper.clone().ok_or(Error::UnexpectedError)?;
+        let left_type = self.node_type_of(left.child)?;
+        let right_type = self.node_type_of(right.child)?;
+        match (left_type, right_type) {
+            (NodeType::Leaf(mut left_pairs), NodeType::Leaf(right_pairs)) => {
+                left_pairs.extend(right_pairs);
+                Ok(self.split_leaf(left_pairs, right.upper.as_ref()))
+            }
+            (
+                NodeType::Internal(left_children, left_keys, left_counts),
+                NodeType::Internal(right_children, right_keys, right_counts),
+            ) => {
+                let junction = left_children.len();
+                let uppers = left_keys
+                    .into_iter()
+                    .chain(iter::once(separator))
+                    .chain(right_keys)
+                    .map(Some)
+                    .chain(iter::once(right.upper.clone()));
+                let counts = left_counts.into_iter().chain(right_counts);
+                let mut parts: Vec<Part> = left_children
+                    .into_iter()
+                    .chain(right_children)
+                    .zip(counts)
+                    .zip(uppers)
+                    .map(|((offset, count), upper)| Part {
+                        child: Child::Kept(offset, count),
+                        upper,
+                    })
+                    .collect();
+                // A node rewritten with a single child could not merge that child with a sibling,
+                // so the children on either side of the junction may underflow as well.
+                for idx in [junction - 1, junction] {
+                    if let Child::Kept(offset, _) = &parts[idx].child {
+                        let node_type = self.pager.get_node(offset)?.node_type;
+                        if self.is_underflow(&node_type)? {
+                            parts[idx].child = Child::New(node_type);
+                        }
+                    }
+                }
+                self.fix_underflows(&mut parts)?;
+                self.build_parts(parts, right.upper)
+            }
+            _ => Err(Error::UnexpectedError),
+        }
+    }
+
+    /// node_type_of returns the content of a sub tree's root, reading it if it was left untouched.
+    fn node_type_of(&mut self, child: Child) -> Result<NodeType, Error> {
+        match child {
+            Child::Kept(offset, _) => Ok(self.pager.get_node(&offset)?.node_type),
+            Child::New(node_type) => Ok(node_type),
+        }
+    }
+
+    /// build_parts writes the new sub trees and groups all of them evenly
+    /// into as few (unwritten) internal nodes as can hold them.
+    fn build_parts(&mut self, parts: Vec<Part>, upper: Option<Key>) -> Result<Vec<Part>, Error> {
+        let max = 2 * self.b;
+        let count = parts.len().div_ceil(max);
+        let mut res = Vec::with_capacity(count);
+        let mut parts = parts.into_iter();
+        for idx in 0..count {
+            let mut children = Vec::new();
+            let mut keys = Vec::new();
+            let mut counts = Vec::new();
+            let size = share(idx, count, parts.len());
+            for part in parts.by_ref().take(size) {
+                let (child_offset, child_count) = match part.child {
+                    Child::Kept(offset, child_count) => (offset, child_count),
+                    Child::New(node_type) => {
+                        let child_count = node_type.count();
+                        let node = Node::new(node_type, false);
+                        (self.pager.write_page(Page::try_from(&node)?)?, child_count)
+                    }
+                };
+                children.push(child_offset);
+                counts.push(child_count);
+                keys.push(part.upper);
+            }
+            // The bound of the last child is the bound of the node itself.
+            let last_upper = keys.pop().ok_or(Error::UnexpectedError)?;
+            let keys = keys
+                .into_iter()
+                .collect::<Option<Vec<Key>>>()
+                .ok_or(Error::UnexpectedError)?;
+            res.push(Part {
+                child: Child::New(NodeType::Internal(children, keys, counts)),
+                upper: match idx + 1 == count {
+                    true => upper.clone(),
+                    false => last_upper,
+                },
+            });
+        }
+        Ok(res)
+    }
+}
+
+/// share returns the number of items the given chunk receives when remaining items
+/// are spread evenly over the chunks from the given index onwards.
+fn share(idx: usize, count: usize, remaining: usize) -> usize {
+    remaining.div_ceil(count - idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn bulk_load_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        for b in 2..5 {
+            for len in 0..60 {
+                for fill_factor in [0.5, 0.75, 1.0] {
+                    let mut btree = BTreeBuilder::new()
+                        .storage(MemoryStorage::new(), MemoryStorage::new())
+                        .b_parameter(b)
+                        .build()?;
+                    let pairs: Vec<KeyValuePair> = (0..len)
+                        .map(|i| KeyValuePair::new(format!("{:03}", i), i.to_string()))
+                        .collect();
+                    btree.bulk_load(pairs.clone(), fill_factor)?;
+
+                    assert_eq!(btree.check()?, vec![], "b {} len {}", b, len);
+                    assert_eq!(btree.scan(..)?, pairs);
+                    assert_eq!(btree.roots()?.len(), 2);
+                    for pair in &pairs {
+                        assert_eq!(btree.search(pair.key.clone())?, *pair);
+                    }
+                    // The loaded tree accepts further writes.
+                    btree.insert(KeyValuePair::new("x".to_string(), "y".to_string()))?;
+                    assert!(btree.check()?.is_empty());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_load_rejects_invalid_input() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        let pair = |key: &str| KeyValuePair::new(key.to_string(), key.to_string());
+
+        let res = btree.bulk_load(vec![pair("a"), pair("c"), pair("b")], 1.0);
+        assert!(matches!(res, Err(Error::KeyOutOfOrder)));
+        let res = btree.bulk_load(vec![pair("a"), pair("b"), pair("b")], 1.0);
+        assert!(matches!(res, Err(Error::KeyAlreadyExists)));
+        // Nothing was published by the failed loads.
+        assert!(btree.scan(..)?.is_empty());
+
+        btree.insert(pair("a"))?;
+        let res = btree.bulk_load(vec![pair("b")], 1.0);
+        assert!(matches!(res, Err(Error::TreeNotEmpty)));
+        Ok(())
+    }
+
+    #[test]
+    fn merge_batch_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::bulk::BatchOp;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::storage::MemoryStorage;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        use std::collections::BTreeMap;
+
+        let mut rng = StdRng::seed_from_u64(3);
+        for b in 2..5 {
+            let mut btree = BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(b)
+                .build()?;
+            let mut model = BTreeMap::new();
+            for round in 0..40 {
+                // Batches range from a handful of keys to most of the key space.
+                let size = rng.gen_range(0..=200);
+                // Every few rounds delete a contiguous run of keys, emptying whole sub trees.
+                let run = match round % 4 == 3 {
+                    true => Some(rng.gen_range(0..300)),
+                    false => None,
+                };
+                let mut ops = BTreeMap::new();
+                for idx in 0..size {
+                    let key = match run {
+                        Some(start) => format!("k{:03}", (start + idx) % 300),
+                        None => format!("k{:03}", rng.gen_range(0..300)),
+                    };
+                    let op = match run.is_none() && rng.gen_bool(0.6) {
+                        true => {
+                            let kv = KeyValuePair::new(key.clone(), format!("v{}", round));
+                            model.insert(key.clone(), kv.clone());
+                            BatchOp::Put(kv)
+                        }
+                        false => {
+                            model.remove(&key);
+                            BatchOp::Delete(Key(key.clone()))
+                        }
+                    };
+                    ops.insert(key, op);
+                }
+                btree.merge_batch(ops.into_values())?;
+
+                assert_eq!(btree.check()?, vec![], "b {} round {}", b, round);
+                let expected: Vec<KeyValuePair> = model.values().cloned().collect();
+                assert_eq!(btree.scan(..)?, expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn delete_range_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        use std::collections::BTreeMap;
+        use std::ops::Bound;
+
+        let mut rng = StdRng::seed_from_u64(5);
+        for b in 2..5 {
+            let mut btree = BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(b)
+                .build()?;
+            let mut model = BTreeMap::new();
+            for round in 0..60 {
+                // Refill the tree every few rounds so that ranges keep hitting keys.
+                if round % 6 == 0 {
+                    for _ in 0..150 {
+                        let key = format!("k{:03}", rng.gen_range(0..300));
+                        if model.contains_key(&key) {
+                            continue;
+                        }
+                        let kv = KeyValuePair::new(key.clone(), key.clone());
+                        btree.insert(kv.clone())?;
+                        model.insert(key, kv);
+                    }
+                }
+                let bound = |rng: &mut StdRng| {
+                    let key = format!("k{:03}", rng.gen_range(0..300));
+                    match rng.gen_range(0..5) {
+                        0 => Bound::Unbounded,
+                        1 | 2 => Bound::Included(key),
+                        _ => Bound::Excluded(key),
+                    }
+                };
+                let start = bound(&mut rng);
+                let mut end = bound(&mut rng);
+                let empty = match (&start, &end) {
+                    (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+                    (
+                        Bound::Included(s) | Bound::Excluded(s),
+                        Bound::Included(e) | Bound::Excluded(e),
+                    ) => s > e,
+                    _ => false,
+                };
+                if empty {
+                    end = Bound::Unbounded;
+                }
+                let range = (start, end);
+                let expected: Vec<String> =
+                    model.range(range.clone()).map(|(k, _)| k.clone()).collect();
+                let roots = btree.roots()?.len();
+
+                assert_eq!(btree.delete_range(range)?, expected.len());
+                for key in &expected {
+                    model.remove(key);
+                }
+                assert_eq!(btree.check()?, vec![], "b {} round {}", b, round);
+                let pairs: Vec<KeyValuePair> = model.values().cloned().collect();
+                assert_eq!(btree.scan(..)?, pairs);
+                // Deleting nothing publishes no new root.
+                if expected.is_empty() {
+                    assert_eq!(btree.roots()?.len(), roots);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sweep_expired_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(50);
+        for b in 2..5 {
+            let mut btree = BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(b)
+                .build()?;
+            let mut pairs = Vec::new();
+            for i in 0..300 {
+                let key = format!("k{:03}", i);
+                // Runs of expired pairs empty whole leaves and sub trees.
+                pairs.push(match (i / 20) % 3 == 0 || rng.gen_bool(0.3) {
+                    true => KeyValuePair::with_expiry(key.clone(), key, 1),
+                    false => KeyValuePair::new(key.clone(), key),
+                });
+            }
+            let mut live: Vec<_> = pairs
+                .iter()
+                .filter(|kv| kv.expires_at.is_none())
+                .cloned()
+                .collect();
+            // Inserts drop the expired pairs of the leaves they rewrite, so load them in bulk.
+            btree.bulk_load(pairs, 1.0)?;
+
+            // Deleting a range drops the expired pairs of the leaves it rewrites as well,
+            // but only counts the live ones.
+            let in_range = live.iter().filter(|kv| kv.key.as_str() < "k050").count();
+            assert_eq!(btree.delete_range(.."k050".to_string())?, in_range);
+            live.drain(..in_range);
+            assert_eq!(btree.check()?, vec![]);
+            assert_eq!(btree.scan(..)?, live);
+
+            assert_eq!(btree.len()?, live.len());
+            let expired = btree.stats()?.keys - live.len();
+            assert!(expired > 0);
+            assert_eq!(btree.sweep_expired()?, expired);
+            assert_eq!(btree.check()?, vec![]);
+            assert_eq!(btree.len()?, live.len());
+            assert_eq!(btree.scan(..)?, live);
+
+            // Nothing is published once there is nothing left to sweep.
+            let roots = btree.roots()?.len();
+            assert_eq!(btree.sweep_expired()?, 0);
+            assert_eq!(btree.roots()?.len(), roots);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merge_batch_rejects_unsorted_batch() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::bulk::BatchOp;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        let put = |key: &str| BatchOp::Put(KeyValuePair::new(key.to_string(), key.to_string()));
+        btree.merge_batch(vec![put("a"), put("b"), put("c")])?;
+        let roots = btree.roots()?.len();
+
+        let res = btree.merge_batch(vec![put("d"), put("b")]);
+        assert!(matches!(res, Err(Error::KeyOutOfOrder)));
+        let res = btree.merge_batch(vec![put("d"), BatchOp::Delete(Key("d".to_string()))]);
+        assert!(matches!(res, Err(Error::KeyAlreadyExists)));
+        // Neither the failed merges nor one changing nothing publish a new root.
+        btree.merge_batch(vec![put("a"), BatchOp::Delete(Key("z".to_string()))])?;
+        assert_eq!(btree.roots()?.len(), roots);
+        assert_eq!(btree.scan(..)?.len(), 3);
+        Ok(())
+    }
+}
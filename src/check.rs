@@ -0,0 +1,374 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node::Node;
+use crate::node_type::{Count, Key, NodeType, Offset};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+/// Violation is a single inconsistency found in the page at a given offset.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Violation {
+    pub offset: Offset,
+    pub kind: ViolationKind,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ViolationKind {
+    /// The page could not be read from the storage.
+    Unreadable,
+    /// The page does not match its checksum.
+    ChecksumMismatch,
+    /// The page passed its checksum but does not hold a valid node.
+    Malformed,
+    /// The page is reachable more than once from the root.
+    SharedPage,
+    /// The keys of the node are not strictly increasing.
+    KeysNotSorted,
+    /// A key lies outside of the range its ancestors' separator keys route to the node,
+    /// i.e. (lower, upper].
+    KeyOutOfBounds {
+        key: Key,
+        lower: Option<Key>,
+        upper: Option<Key>,
+    },
+    /// An internal node does not have exactly one child more than it has keys.
+    ChildCountMismatch { children: usize, keys: usize },
+    /// A non-root node holds less than b-1 keys.
+    Underflow { keys: usize, min: usize },
+    /// A node holds more than 2*b-1 keys.
+    Overflow { keys: usize, max: usize },
+    /// An empty internal root.
+    EmptyRoot,
+    /// A leaf lies at a different depth than the first leaf found.
+    UnevenLeafDepth { depth: usize, expected: usize },
+    /// The is_root flag of the node does not match its position in the tree.
+    RootFlagMismatch { is_root: bool },
+    /// The number of pairs an internal node stores for a child differs from the pairs under it.
+    PairCountMismatch {
+        child: usize,
+        stored: usize,
+        actual: usize,
+    },
+    /// The earliest expiry an internal node stores for a child differs from the one of the pairs under it.
+    ExpiryMismatch {
+        child: usize,
+        stored: u64,
+        actual: u64,
+    },
+}
+
+/// Checker accumulates state over a single walk of the tree.
+struct Checker {
+    visited: HashSet<usize>,
+    leaf_depth: Option<usize>,
+    violations: Vec<Violation>,
+}
+
+impl Checker {
+    fn report(&mut self, offset: &Offset, kind: ViolationKind) {
+        self.violations.push(Violation {
+            offset: offset.clone(),
+            kind,
+        });
+    }
+}
+
+impl BTree {
+    /// check walks the tree from its current root and reports every violation
+    /// of the B+Tree invariants it finds along with the offset of the offending page.
+    /// An empty result means the tree is consistent.
+    pub fn check(&mut self) -> Result<Vec<Violation>, Error> {
+        let root_offset = self.wal.get_root()?;
+        let mut checker = Checker {
+            visited: HashSet::new(),
+            leaf_depth: None,
+            violations: Vec::new(),
+        };
+        self.check_sub_tree(&mut checker, root_offset, true, None, None, 0);
+        Ok(checker.violations)
+    }
+
+    /// check_sub_tree recursively checks the nodes rooted at a node given by its offset,
+    /// all keys in the sub tree are expected to lie within (lower, upper].
+    /// It returns the count of the pairs in the sub tree, unless some of its pages could not be read.
+    fn check_sub_tree(
+        &mut self,
+        checker: &mut Checker,
+        offset: Offset,
+        is_root: bool,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+        depth: usize,
+    ) -> Option<Count> {
+        if !checker.visited.insert(offset.0) {
+            checker.report(&offset, ViolationKind::SharedPage);
+            return None;
+        }
+        let kind = match self.pager.get_page(&offset) {
+            Ok(page) => match Node::try_from(page) {
+                Ok(node) => Ok(node),
+                Err(_) => Err(ViolationKind::Malformed),
+            },
+            Err(Error::Corruption { .. }) => Err(ViolationKind::ChecksumMismatch),
+            Err(_) => Err(ViolationKind::Unreadable),
+        };
+        let node = match kind {
+            Ok(node) => node,
+            Err(kind) => {
+                checker.report(&offset, kind);
+                return None;
+            }
+        };
+
+        if node.is_root != is_root {
+            checker.report(
+                &offset,
+                ViolationKind::RootFlagMismatch {
+                    is_root: node.is_root,
+                },
+            );
+        }
+
+        let keys: Vec<Key> = match &node.node_type {
+            NodeType::Internal(_, keys, _) => keys.clone(),
+            NodeType::Leaf(pairs) => pairs.iter().map(|kv| Key(kv.key.clone())).collect(),
+            NodeType::Unexpected => {
+                checker.report(&offset, ViolationKind::Malformed);
+                return None;
+            }
+        };
+        self.check_keys(checker, &offset, &keys, is_root, lower, upper);
+
+        match node.node_type {
+            NodeType::Internal(children, keys, counts) => {
+                if children.len() != keys.len() + 1 {
+                    checker.report(
+                        &offset,
+                        ViolationKind::ChildCountMismatch {
+                            children: children.len(),
+                            keys: keys.len(),
+                        },
+                    );
+                }
+                if is_root && keys.is_empty() {
+                    checker.report(&offset, ViolationKind::EmptyRoot);
+                }
+                let mut total = Some(Count::default());
+                for (idx, (child_offset, stored)) in children.into_iter().zip(counts).enumerate() {
+                    let child_lower = match idx {
+                        0 => lower,
+                        _ => keys.get(idx - 1).or_else(|| keys.last()).or(lower),
+                    };
+                    let child_upper = keys.get(idx).or(upper);
+                    let actual = self.check_sub_tree(
+                        checker,
+                        child_offset,
+                        false,
+                        child_lower,
+                        child_upper,
+                        depth + 1,
+                    );
+                    if let Some(actual) = actual {
+                        if actual.pairs != stored.pairs {
+                            checker.report(
+                                &offset,
+                                ViolationKind::PairCountMismatch {
+                                    child: idx,
+                                    stored: stored.pairs,
+                                    actual: actual.pairs,
+                                },
+                            );
+                        }
+                        if actual.earliest_expiry != stored.earliest_expiry {
+                            checker.report(
+                                &offset,
+                                ViolationKind::ExpiryMismatch {
+                                    child: idx,
+                                    stored: stored.earliest_expiry,
+                                    actual: actual.earliest_expiry,
+                                },
+                            );
+                        }
+                    }
+                    total = total.zip(actual).map(|(total, actual)| total + actual);
+                }
+                total
+            }
+            NodeType::Leaf(pairs) => {
+                match checker.leaf_depth {
+                    Some(expected) if expected != depth => {
+                        checker.report(&offset, ViolationKind::UnevenLeafDepth { depth, expected })
+                    }
+                    Some(_) => {}
+                    None => checker.leaf_depth = Some(depth),
+                }
+                Some(Count::of(&pairs))
+            }
+            NodeType::Unexpected => None,
+        }
+    }
+
+    /// check_keys checks the keys of a single node for order, bounds and occupancy.
+    fn check_keys(
+        &self,
+        checker: &mut Checker,
+        offset: &Offset,
+        keys: &[Key],
+        is_root: bool,
+        lower: Option<&Key>,
+        upper: Option<&Key>,
+    ) {
+        if keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+            checker.report(offset, ViolationKind::KeysNotSorted);
+        }
+        for key in keys {
+            let above_lower = lower.is_none_or(|lower| key > lower);
+            let below_upper = upper.is_none_or(|upper| key <= upper);
+            if !above_lower || !below_upper {
+                checker.report(
+                    offset,
+                    ViolationKind::KeyOutOfBounds {
+                        key: key.clone(),
+                        lower: lower.cloned(),
+                        upper: upper.cloned(),
+                    },
+                );
+            }
+        }
+        let (min, max) = (self.b - 1, 2 * self.b - 1);
+        if keys.len() > max {
+            checker.report(
+                offset,
+                ViolationKind::Overflow {
+                    keys: keys.len(),
+                    max,
+                },
+            );
+        }
+        if !is_root && keys.len() < min {
+            checker.report(
+                offset,
+                ViolationKind::Underflow {
+                    keys: keys.len(),
+                    min,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn check_passes_on_valid_tree() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        assert!(btree.check()?.is_empty());
+        for i in 0..50 {
+            btree.insert(KeyValuePair::new(format!("{:03}", i), i.to_string()))?;
+        }
+        assert!(btree.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_passes_after_replacing_keys() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        // Inserting a key which is already in the tree replaces its pair instead of duplicating it.
+        for round in 0..3 {
+            for i in 0..30 {
+                btree.insert(KeyValuePair::new(format!("{:03}", i), round.to_string()))?;
+            }
+        }
+        assert!(btree.check()?.is_empty());
+        assert_eq!(btree.len()?, 30);
+        for i in 0..30 {
+            assert_eq!(btree.search(format!("{:03}", i))?.value, "2");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn check_reports_violations() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::check::ViolationKind;
+        use crate::node::Node;
+        use crate::node_type::{Count, Key, KeyValuePair, NodeType, Offset};
+        use crate::page::Page;
+        use crate::storage::MemoryStorage;
+        use std::convert::TryFrom;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for key in ["a", "b", "c", "d"] {
+            btree.insert(KeyValuePair::new(key.to_string(), key.to_string()))?;
+        }
+
+        // Replace the root with one whose separator is out of order with its children,
+        // which miscounts the pairs of its first child, gives an expiry to its second child
+        // and points at a page past the end of the storage.
+        let root_offset = btree.wal.get_root()?;
+        let root = btree.pager.get_node(&root_offset)?;
+        let (mut children, mut counts) = match root.node_type {
+            NodeType::Internal(children, _, counts) => (children, counts),
+            _ => return Err(Error::UnexpectedError),
+        };
+        counts[0].pairs += 1;
+        counts[1].earliest_expiry = 1;
+        children.push(Offset(1 << 20));
+        counts.push(Count {
+            pairs: 1,
+            ..Count::default()
+        });
+        let bad_root = Node::new(
+            NodeType::Internal(
+                children,
+                vec![Key("z".to_string()), Key("y".to_string())],
+                counts,
+            ),
+            true,
+        );
+        let bad_root_offset = btree.pager.write_page(Page::try_from(&bad_root)?)?;
+        btree.wal.set_root(bad_root_offset.clone())?;
+
+        let violations = btree.check()?;
+        let kinds: Vec<&ViolationKind> = violations
+            .iter()
+            .filter(|v| v.offset == bad_root_offset)
+            .map(|v| &v.kind)
+            .collect();
+        assert!(kinds.contains(&&ViolationKind::KeysNotSorted));
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, ViolationKind::PairCountMismatch { child: 0, .. })));
+        assert!(kinds.contains(&&ViolationKind::ExpiryMismatch {
+            child: 1,
+            stored: 1,
+            actual: u64::MAX
+        }));
+        assert!(violations
+            .iter()
+            .any(|v| v.offset == Offset(1 << 20) && v.kind == ViolationKind::Unreadable));
+        assert!(violations
+            .iter()
+            .any(|v| matches!(v.kind, ViolationKind::KeyOutOfBounds { .. })));
+        Ok(())
+    }
+}
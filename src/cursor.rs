@@ -0,0 +1,415 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node::Node;
+use crate::node_type::{now, Key, KeyValuePair, NodeType, Offset};
+
+/// Cursor is a position among the key-value pairs of a tree which can be moved in both
+/// directions and used to modify the pair it points at.
+///
+/// Like [`Range`](crate::iter::Range), which explains why leaves do not link to their siblings,
+/// the cursor keeps the internal nodes on the path to the current leaf on a stack,
+/// so stepping to an adjacent leaf only reads the nodes which differ between the two paths.
+/// A cursor which moved past either end of the tree points at no pair until it is seeked again.
+/// Pairs which expired by the time the cursor was created are skipped.
+pub struct Cursor<'a> {
+    btree: &'a mut BTree,
+    now: u64,
+    /// Internal nodes on the path to the current leaf,
+    /// along with the index of the child the cursor descended to.
+    stack: Vec<(Node, usize)>,
+    leaf: Node,
+    /// Index of the current pair within the leaf, None if the cursor points at no pair.
+    idx: Option<usize>,
+}
+
+/// Edge is the side of a sub tree a descent heads to.
+#[derive(Clone, Copy)]
+enum Edge {
+    First,
+    Last,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(btree: &'a mut BTree) -> Self {
+        Cursor {
+            btree,
+            now: now(),
+            stack: Vec::new(),
+            leaf: Node::new(NodeType::Leaf(vec![]), false),
+            idx: None,
+        }
+    }
+
+    /// seek moves the cursor to the first pair whose key is greater than or equal to the given key,
+    /// returning false if there is no such pair.
+    pub fn seek(&mut self, key: &str) -> Result<bool, Error> {
+        self.stack.clear();
+        self.leaf = self.btree.find_leaf(key, &mut self.stack)?;
+        let idx = self.pairs()?.partition_point(|kv| kv.key.as_str() < key);
+        self.forward_to(idx)
+    }
+
+    /// seek_to_first moves the cursor to the smallest key of the tree,
+    /// returning false if the tree is empty.
+    pub fn seek_to_first(&mut self) -> Result<bool, Error> {
+        self.stack.clear();
+        let root_offset = self.btree.wal.get_root()?;
+        self.descend(root_offset, Edge::First)?;
+        self.forward_to(0)
+    }
+
+    /// seek_to_last moves the cursor to the largest key of the tree,
+    /// returning false if the tree is empty.
+    pub fn seek_to_last(&mut self) -> Result<bool, Error> {
+        self.stack.clear();
+        let root_offset = self.btree.wal.get_root()?;
+        self.descend(root_offset, Edge::Last)?;
+        let idx = self.pairs()?.len().checked_sub(1);
+        self.backward_to(idx)
+    }
+
+    /// next moves the cursor to the following pair, returning false if it was the last one.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<bool, Error> {
+        match self.idx {
+            Some(idx) => self.forward_to(idx + 1),
+            None => Ok(false),
+        }
+    }
+
+    /// prev moves the cursor to the preceding pair, returning false if it was the first one.
+    pub fn prev(&mut self) -> Result<bool, Error> {
+        match self.idx {
+            Some(idx) => self.backward_to(idx.checked_sub(1)),
+            None => Ok(false),
+        }
+    }
+
+    /// current returns the pair the cursor points at.
+    pub fn current(&self) -> Option<&KeyValuePair> {
+        match &self.leaf.node_type {
+            NodeType::Leaf(pairs) => pairs.get(self.idx?),
+            _ => None,
+        }
+    }
+
+    /// pair returns a copy of the current pair, or KeyNotFound if the cursor points at no pair.
+    fn pair(&self) -> Result<KeyValuePair, Error> {
+        self.current().cloned().ok_or(Error::KeyNotFound)
+    }
+
+    /// update_value replaces the value of the current pair, copying the nodes on the path to it.
+    pub fn update_value(&mut self, value: String) -> Result<(), Error> {
+        let mut kv = self.pair()?;
+        kv.value = value;
+        let key = kv.key.clone();
+        // Rewriting the leaf drops its expired pairs, which may rebalance the nodes on the path,
+        // so the pair is inserted again and the cursor seeks it from the new root.
+        self.btree.insert(kv)?;
+        self.seek(&key)?;
+        Ok(())
+    }
+
+    /// delete_current deletes the current pair and moves the cursor to the following one,
+    /// returning false if the deleted pair was the last one.
+    pub fn delete_current(&mut self) -> Result<bool, Error> {
+        let key = self.current().ok_or(Error::KeyNotFound)?.key.clone();
+        // Deleting may rebalance the nodes on the path, so the cursor seeks again from the new root.
+        self.btree.delete(Key(key.clone()))?;
+        self.seek(&key)
+    }
+
+    /// forward_to moves the cursor to the first unexpired pair from the given index of the current leaf
+    /// onwards, moving on to the following leaves if there is none in the current leaf.
+    fn forward_to(&mut self, idx: usize) -> Result<bool, Error> {
+        let mut idx = idx;
+        loop {
+            while idx >= self.pairs()?.len() {
+                if !self.step(Edge::First)? {
+                    return Ok(self.reset());
+                }
+                idx = 0;
+            }
+            if !self.pairs()?[idx].is_expired(self.now) {
+                self.idx = Some(idx);
+                return Ok(true);
+            }
+            idx += 1;
+        }
+    }
+
+    /// backward_to moves the cursor to the last unexpired pair up to the given index of the current leaf,
+    /// moving on to the preceding leaves if there is none (or no index) in the current leaf.
+    fn backward_to(&mut self, idx: Option<usize>) -> Result<bool, Error> {
+        let mut idx = idx;
+        loop {
+            if let Some(pair_idx) = idx {
+                if !self.pairs()?[pair_idx].is_expired(self.now) {
+                    self.idx = Some(pair_idx);
+                    return Ok(true);
+                }
+                idx = pair_idx.checked_sub(1);
+                continue;
+            }
+            if !self.step(Edge::Last)? {
+                return Ok(self.reset());
+            }
+            idx = self.pairs()?.len().checked_sub(1);
+        }
+    }
+
+    /// step moves to the adjacent leaf, the one following the current leaf when heading to the
+    /// first leaf of the next sub tree and the one preceding it otherwise.
+    /// Returns false if the current leaf is the last (or first) one.
+    fn step(&mut self, edge: Edge) -> Result<bool, Error> {
+        while let Some((node, idx)) = self.stack.last_mut() {
+            let children = match &node.node_type {
+                NodeType::Internal(children, _, _) => children,
+                _ => return Err(Error::UnexpectedError),
+            };
+            let sibling = match edge {
+                Edge::First => Some(*idx + 1).filter(|idx| *idx < children.len()),
+                Edge::Last => idx.checked_sub(1),
+            };
+            if let Some(sibling) = sibling {
+                let offset = children[sibling].clone();
+                *idx = sibling;
+                self.descend(offset, edge)?;
+                return Ok(true);
+            }
+            self.stack.pop();
+        }
+        Ok(false)
+    }
+
+    /// descend descends from the node at the given offset to its first (or last) leaf.
+    fn descend(&mut self, offset: Offset, edge: Edge) -> Result<(), Error> {
+        let mut node = self.read(&offset)?;
+        while let NodeType::Internal(children, _, _) = &node.node_type {
+            let idx = match edge {
+                Edge::First => 0,
+                Edge::Last => children.len().saturating_sub(1),
+            };
+            let child_offset = children.get(idx).ok_or(Error::UnexpectedError)?;
+            let child = self.read(child_offset)?;
+            self.stack.push((node, idx));
+            node = child;
+        }
+        self.leaf = node;
+        Ok(())
+    }
+
+    /// reset leaves the cursor pointing at no pair, returning false for convenience.
+    fn reset(&mut self) -> bool {
+        self.stack.clear();
+        self.leaf = Node::new(NodeType::Leaf(vec![]), false);
+        self.idx = None;
+        false
+    }
+
+    fn read(&mut self, offset: &Offset) -> Result<Node, Error> {
+        self.btree.pager.get_node(offset)
+    }
+
+    fn pairs(&self) -> Result<&Vec<KeyValuePair>, Error> {
+        match &self.leaf.node_type {
+            NodeType::Leaf(pairs) => Ok(pairs),
+            _ => Err(Error::UnexpectedError),
+        }
+    }
+}
+
+impl BTree {
+    /// cursor returns a cursor over the tree, it points at no pair until it is seeked.
+    pub fn cursor(&mut self) -> Cursor<'_> {
+        Cursor::new(self)
+    }
+
+    /// first returns the pair with the smallest key of the tree, or KeyNotFound if it is empty.
+    pub fn first(&mut self) -> Result<KeyValuePair, Error> {
+        let mut cursor = self.cursor();
+        cursor.seek_to_first()?;
+        cursor.pair()
+    }
+
+    /// last returns the pair with the largest key of the tree, or KeyNotFound if it is empty.
+    pub fn last(&mut self) -> Result<KeyValuePair, Error> {
+        let mut cursor = self.cursor();
+        cursor.seek_to_last()?;
+        cursor.pair()
+    }
+
+    /// floor returns the pair with the greatest key smaller than or equal to the given key.
+    pub fn floor(&mut self, key: &str) -> Result<KeyValuePair, Error> {
+        self.before(key, true)
+    }
+
+    /// ceiling returns the pair with the least key greater than or equal to the given key.
+    pub fn ceiling(&mut self, key: &str) -> Result<KeyValuePair, Error> {
+        self.after(key, true)
+    }
+
+    /// lower returns the pair with the greatest key strictly smaller than the given key.
+    pub fn lower(&mut self, key: &str) -> Result<KeyValuePair, Error> {
+        self.before(key, false)
+    }
+
+    /// higher returns the pair with the least key strictly greater than the given key.
+    pub fn higher(&mut self, key: &str) -> Result<KeyValuePair, Error> {
+        self.after(key, false)
+    }
+
+    /// before returns the pair with the greatest key smaller than the given key
+    /// (or equal to it if inclusive), or KeyNotFound if there is none.
+    fn before(&mut self, key: &str, inclusive: bool) -> Result<KeyValuePair, Error> {
+        let mut cursor = self.cursor();
+        // The cursor lands on the least key greater than or equal to the given one,
+        // the pair preceding it (possibly in the previous leaf) is the greatest smaller one.
+        if !cursor.seek(key)? {
+            cursor.seek_to_last()?;
+        } else if !(inclusive && cursor.current().is_some_and(|kv| kv.key == key)) {
+            cursor.prev()?;
+        }
+        cursor.pair()
+    }
+
+    /// after returns the pair with the least key greater than the given key
+    /// (or equal to it if inclusive), or KeyNotFound if there is none.
+    fn after(&mut self, key: &str, inclusive: bool) -> Result<KeyValuePair, Error> {
+        let mut cursor = self.cursor();
+        if cursor.seek(key)? && !inclusive && cursor.current().is_some_and(|kv| kv.key == key) {
+            cursor.next()?;
+        }
+        cursor.pair()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn cursor_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        {
+            let mut cursor = btree.cursor();
+            assert!(!cursor.seek_to_first()?);
+            assert!(!cursor.seek_to_last()?);
+            assert!(cursor.current().is_none());
+        }
+        let keys: Vec<String> = (0..40).map(|i| format!("{:03}", i * 2)).collect();
+        for key in &keys {
+            btree.insert(KeyValuePair::new(key.clone(), key.clone()))?;
+        }
+
+        let mut cursor = btree.cursor();
+        let mut forward = Vec::new();
+        let mut valid = cursor.seek_to_first()?;
+        while valid {
+            forward.push(cursor.current().unwrap().key.clone());
+            valid = cursor.next()?;
+        }
+        assert_eq!(forward, keys);
+        assert!(cursor.current().is_none());
+
+        let mut backward = Vec::new();
+        let mut valid = cursor.seek_to_last()?;
+        while valid {
+            backward.push(cursor.current().unwrap().key.clone());
+            valid = cursor.prev()?;
+        }
+        assert_eq!(backward, keys.iter().rev().cloned().collect::<Vec<_>>());
+
+        // Seeking lands on the first key greater than or equal to the target.
+        assert!(cursor.seek("010")?);
+        assert_eq!(cursor.current().unwrap().key, "010");
+        assert!(cursor.seek("011")?);
+        assert_eq!(cursor.current().unwrap().key, "012");
+        assert!(cursor.prev()? && cursor.prev()?);
+        assert_eq!(cursor.current().unwrap().key, "008");
+        assert!(!cursor.seek("079")?);
+
+        // Update every pair and delete every other one while walking the tree.
+        assert!(cursor.seek_to_first()?);
+        loop {
+            cursor.update_value("updated".to_string())?;
+            if !cursor.next()? || !cursor.delete_current()? {
+                break;
+            }
+        }
+        assert!(btree.check()?.is_empty());
+        let remaining = btree.scan(..)?;
+        let expected: Vec<KeyValuePair> = keys
+            .iter()
+            .step_by(2)
+            .map(|key| KeyValuePair::new(key.clone(), "updated".to_string()))
+            .collect();
+        assert_eq!(remaining, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn nearest_keys_work() -> Result<(), Error> {
+        use crate::btree::{BTree, BTreeBuilder};
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        type Lookup = fn(&mut BTree, &str) -> Result<KeyValuePair, Error>;
+        let lookups: [Lookup; 4] = [BTree::floor, BTree::ceiling, BTree::lower, BTree::higher];
+        assert!(matches!(btree.first(), Err(Error::KeyNotFound)));
+        assert!(matches!(btree.last(), Err(Error::KeyNotFound)));
+        for lookup in lookups {
+            assert!(matches!(lookup(&mut btree, "a"), Err(Error::KeyNotFound)));
+        }
+
+        // Keys 010, 020, ..., 300 spread over many leaves.
+        let mut keys: Vec<String> = (1..=30).map(|i| format!("{:03}", i * 10)).collect();
+        for key in &keys {
+            btree.insert(KeyValuePair::new(key.clone(), key.clone()))?;
+        }
+        assert_eq!(btree.first()?.key, "010");
+        assert_eq!(btree.last()?.key, "300");
+        // Deleting keys leaves separators which are no longer in the tree.
+        for key in keys.iter().step_by(3) {
+            btree.delete(Key(key.clone()))?;
+        }
+        keys = keys
+            .into_iter()
+            .enumerate()
+            .filter(|(idx, _)| idx % 3 != 0)
+            .map(|(_, key)| key)
+            .collect();
+        assert_eq!(btree.scan(..)?.len(), keys.len());
+
+        // Compare every lookup with a scan of the keys, on and between the keys of the tree.
+        for i in 0..=310 {
+            let target = format!("{:03}", i);
+            let expected = [
+                keys.iter().rev().find(|key| **key <= target),
+                keys.iter().find(|key| **key >= target),
+                keys.iter().rev().find(|key| **key < target),
+                keys.iter().find(|key| **key > target),
+            ];
+            for (lookup, expected) in lookups.iter().zip(expected) {
+                let found = match lookup(&mut btree, &target) {
+                    Ok(kv) => Some(kv.key),
+                    Err(Error::KeyNotFound) => None,
+                    Err(e) => return Err(e),
+                };
+                assert_eq!(found.as_ref(), expected, "target {}", target);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{NodeType, Offset};
+use std::collections::HashSet;
+use std::io::Write;
+
+/// DotOptions controls what to_dot renders.
+#[derive(Clone, Debug, Default)]
+pub struct DotOptions {
+    /// Render leaves with their number of pairs rather than the pairs themselves.
+    pub elide_leaf_contents: bool,
+    /// Render the difference between the current tree and the tree rooted at this offset
+    /// (e.g. a previous root returned by BTree::roots): nodes shared by both trees are greyed out,
+    /// nodes only in the current tree are green and nodes only in the other tree are red and dashed.
+    pub diff_against: Option<Offset>,
+}
+
+/// Status of a node when rendering the difference between two trees.
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Plain,
+    Shared,
+    Added,
+    Removed,
+}
+
+impl BTree {
+    /// to_dot writes the current tree as a Graphviz graph to the given writer,
+    /// with a node per page (labeled by its offset and keys) and an edge per child pointer.
+    pub fn to_dot<W: Write>(&mut self, writer: &mut W, options: &DotOptions) -> Result<(), Error> {
+        let root_offset = self.wal.get_root()?;
+        writeln!(writer, "digraph btree {{")?;
+        writeln!(writer, "    node [shape=box, fontname=monospace];")?;
+        match &options.diff_against {
+            None => {
+                self.dot_sub_tree(writer, root_offset, options, &HashSet::new(), Status::Plain)?;
+            }
+            Some(other_root) => {
+                let current = self.reachable_offsets(root_offset.clone())?;
+                let other = self.reachable_offsets(other_root.clone())?;
+                self.dot_sub_tree(writer, root_offset, options, &other, Status::Added)?;
+                self.dot_sub_tree(
+                    writer,
+                    other_root.clone(),
+                    options,
+                    &current,
+                    Status::Removed,
+                )?;
+            }
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    /// dot_sub_tree recursively writes the nodes rooted at a node given by its offset.
+    /// Nodes in `shared` belong to both trees of a diff, they are rendered once
+    /// (as Shared) when rendering the current tree and skipped when rendering the other tree.
+    fn dot_sub_tree<W: Write>(
+        &mut self,
+        writer: &mut W,
+        offset: Offset,
+        options: &DotOptions,
+        shared: &HashSet<usize>,
+        status: Status,
+    ) -> Result<(), Error> {
+        let is_shared = shared.contains(&offset.0);
+        if is_shared && status == Status::Removed {
+            return Ok(());
+        }
+        let node_status = match is_shared {
+            true => Status::Shared,
+            false => status,
+        };
+        let style = match node_status {
+            Status::Plain => "",
+            Status::Shared => ", color=grey, fontcolor=grey",
+            Status::Added => ", color=darkgreen, penwidth=2",
+            Status::Removed => ", color=red, style=dashed",
+        };
+        match self.pager.get_node(&offset)?.node_type {
+            NodeType::Internal(children, keys, _) => {
+                let keys: Vec<&str> = keys.iter().map(|key| key.0.as_str()).collect();
+                let label = format!("offset: {}\\nkeys: {}", offset.0, escape(&keys.join(", ")));
+                writeln!(writer, "    n{} [label=\"{}\"{}];", offset.0, label, style)?;
+                for child_offset in children {
+                    writeln!(writer, "    n{} -> n{};", offset.0, child_offset.0)?;
+                    self.dot_sub_tree(writer, child_offset, options, shared, status)?;
+                }
+            }
+            NodeType::Leaf(pairs) => {
+                let contents = match options.elide_leaf_contents {
+                    true => format!("{} pairs", pairs.len()),
+                    false => {
+                        let pairs: Vec<String> = pairs
+                            .iter()
+                            .map(|pair| format!("{}={}", pair.key, pair.value))
+                            .collect();
+                        escape(&pairs.join(", "))
+                    }
+                };
+                let label = format!("offset: {}\\n{}", offset.0, contents);
+                writeln!(writer, "    n{} [label=\"{}\"{}];", offset.0, label, style)?;
+            }
+            NodeType::Unexpected => return Err(Error::UnexpectedError),
+        }
+        Ok(())
+    }
+
+    /// reachable_offsets returns the offsets of every node in the tree rooted at a given offset.
+    fn reachable_offsets(&mut self, root_offset: Offset) -> Result<HashSet<usize>, Error> {
+        let mut offsets = HashSet::new();
+        let mut stack = vec![root_offset];
+        while let Some(offset) = stack.pop() {
+            if let NodeType::Internal(children, _, _) = self.pager.get_node(&offset)?.node_type {
+                stack.extend(children);
+            }
+            offsets.insert(offset.0);
+        }
+        Ok(offsets)
+    }
+}
+
+/// escape escapes a string to be placed within a double quoted DOT label.
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn to_dot_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::dot::DotOptions;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for key in ["a", "b", "c", "d\"q"] {
+            btree.insert(KeyValuePair::new(key.to_string(), "v".to_string()))?;
+        }
+
+        let mut out = Vec::new();
+        btree.to_dot(&mut out, &DotOptions::default())?;
+        let dot = String::from_utf8(out).map_err(|_| Error::UTF8Error)?;
+        assert!(dot.starts_with("digraph btree {"));
+        assert!(dot.trim_end().ends_with('}'));
+        assert_eq!(dot.matches(" -> ").count(), 2);
+        assert!(dot.contains("a=v, b=v"));
+        assert!(dot.contains("c=v, d\\\"q=v"));
+
+        let mut out = Vec::new();
+        let options = DotOptions {
+            elide_leaf_contents: true,
+            ..DotOptions::default()
+        };
+        btree.to_dot(&mut out, &options)?;
+        let dot = String::from_utf8(out).map_err(|_| Error::UTF8Error)?;
+        assert!(dot.contains("2 pairs"));
+        assert!(!dot.contains("a=v"));
+        Ok(())
+    }
+
+    #[test]
+    fn to_dot_diff_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::dot::DotOptions;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for key in ["a", "b", "c", "d"] {
+            btree.insert(KeyValuePair::new(key.to_string(), "v".to_string()))?;
+        }
+        let previous_root = btree.roots()?.pop().ok_or(Error::UnexpectedError)?;
+        btree.insert(KeyValuePair::new("e".to_string(), "v".to_string()))?;
+
+        let mut out = Vec::new();
+        let options = DotOptions {
+            diff_against: Some(previous_root),
+            ..DotOptions::default()
+        };
+        btree.to_dot(&mut out, &options)?;
+        let dot = String::from_utf8(out).map_err(|_| Error::UTF8Error)?;
+        // The new root and the leaf holding "e" were added, the left leaf is shared
+        // and the previous root and leaf holding "c" and "d" were replaced.
+        assert_eq!(dot.matches("darkgreen").count(), 2);
+        assert_eq!(dot.matches("grey,").count(), 1);
+        assert_eq!(dot.matches("dashed").count(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+use crate::btree::BTree;
+use crate::bulk::BatchOp;
+use crate::error::Error;
+use crate::node_type::{now, KeyValuePair, NodeType};
+use crate::page_layout::{KEY_SIZE, VALUE_SIZE};
+use crate::print::NodeInfo;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Fraction of each node filled when loading into an empty tree, leaving room for later inserts.
+const LOAD_FILL_FACTOR: f64 = 0.9;
+
+/// Format is the file format key-value pairs are dumped to and loaded from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Format {
+    /// One `{"key": ..., "value": ...}` object per line, along with `"expires_at": ...`
+    /// for a pair which expires.
+    #[default]
+    JsonLines,
+    /// A `key,value,expires_at` header followed by one record per pair,
+    /// the expiry being empty for a pair which never expires.
+    Csv,
+}
+
+/// DumpOptions controls how key-value pairs are written by dump and read by load.
+#[derive(Clone, Debug, Default)]
+pub struct DumpOptions {
+    pub format: Format,
+    /// Keys and values are base64 encoded, which keeps arbitrary (e.g. binary) contents
+    /// safe from any tool processing the file.
+    pub base64: bool,
+}
+
+/// Rejected is a row of the input load could not insert into the tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Rejected {
+    /// Line of the row in the input, counting from one.
+    pub line: usize,
+    pub reason: String,
+}
+
+/// LoadReport summarizes the outcome of a load.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct LoadReport {
+    /// Number of pairs inserted into the tree.
+    pub loaded: usize,
+    pub rejected: Vec<Rejected>,
+}
+
+impl BTree {
+    /// dump writes every (unexpired) key-value pair of the tree in key order to the given writer,
+    /// returning the number of pairs written.
+    pub fn dump<W: Write>(&mut self, writer: W, options: &DumpOptions) -> Result<usize, Error> {
+        let mut writer = PairWriter::new(writer, options)?;
+        let mut count = 0;
+        let now = now();
+        // Leaves are visited from left to right, which yields the pairs in key order.
+        self.walk(&mut |node: &NodeInfo| -> Result<bool, Error> {
+            if let NodeType::Leaf(pairs) = &node.node_type {
+                for pair in pairs.iter().filter(|kv| !kv.is_expired(now)) {
+                    writer.write(pair)?;
+                    count += 1;
+                }
+            }
+            Ok(true)
+        })?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// load inserts the key-value pairs read from the given reader into the tree.
+    /// Rows which cannot be parsed or inserted (e.g. keys already in the tree or too large)
+    /// are skipped and reported rather than failing the whole load.
+    /// The whole input is read and checked before the first pair is inserted, and the accepted
+    /// pairs are published with a single root (bulk loaded into an empty tree, merged into another).
+    pub fn load<R: Read>(&mut self, reader: R, options: &DumpOptions) -> Result<LoadReport, Error> {
+        let mut rows = Vec::new();
+        match options.format {
+            Format::JsonLines => {
+                for (idx, line) in BufReader::new(reader).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    rows.push((idx + 1, parse_json(&line, options.base64)));
+                }
+            }
+            Format::Csv => {
+                let mut reader = csv::Reader::from_reader(reader);
+                for record in reader.records() {
+                    match record {
+                        Ok(record) => {
+                            let line = record.position().map_or(0, |pos| pos.line() as usize);
+                            rows.push((line, parse_csv(&record, options.base64)));
+                        }
+                        Err(e) if e.is_io_error() => return Err(std::io::Error::from(e).into()),
+                        Err(e) => {
+                            let line = e.position().map_or(0, |pos| pos.line() as usize);
+                            rows.push((line, Err(e.to_string())));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut report = LoadReport::default();
+        // The first row of a key is kept, later ones are duplicates.
+        let mut pairs: BTreeMap<String, (usize, KeyValuePair)> = BTreeMap::new();
+        for (line, row) in rows {
+            let reason = match row {
+                Ok(kv) if kv.key.len() > KEY_SIZE => "key too large".to_string(),
+                Ok(kv) if kv.value.len() > VALUE_SIZE => "value too large".to_string(),
+                Ok(kv) if pairs.contains_key(&kv.key) => format!("duplicate key: {}", kv.key),
+                Ok(kv) => {
+                    pairs.insert(kv.key.clone(), (line, kv));
+                    continue;
+                }
+                Err(reason) => reason,
+            };
+            report.rejected.push(Rejected { line, reason });
+        }
+        let keys: Vec<&String> = pairs.keys().collect();
+        let existing = self.get_many(&keys)?;
+        let mut accepted = Vec::with_capacity(pairs.len());
+        for ((line, kv), existing) in pairs.into_values().zip(existing) {
+            match existing {
+                Some(_) => report.rejected.push(Rejected {
+                    line,
+                    reason: format!("duplicate key: {}", kv.key),
+                }),
+                None => accepted.push(kv),
+            }
+        }
+        report.rejected.sort_by_key(|rejected| rejected.line);
+        report.loaded = accepted.len();
+
+        // A tree holding expired pairs only is not empty to bulk_load, the pairs are merged into it instead.
+        match self.bulk_load(accepted.iter().cloned(), LOAD_FILL_FACTOR) {
+            Err(Error::TreeNotEmpty) => self.merge_batch(accepted.into_iter().map(BatchOp::Put))?,
+            res => res?,
+        }
+        Ok(report)
+    }
+}
+
+/// PairWriter writes key-value pairs in one of the supported formats.
+enum PairWriter<W: Write> {
+    JsonLines(W, bool),
+    Csv(Box<csv::Writer<W>>, bool),
+}
+
+impl<W: Write> PairWriter<W> {
+    fn new(writer: W, options: &DumpOptions) -> Result<PairWriter<W>, Error> {
+        match options.format {
+            Format::JsonLines => Ok(PairWriter::JsonLines(writer, options.base64)),
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer
+                    .write_record(["key", "value", "expires_at"])
+                    .map_err(|_| Error::UnexpectedError)?;
+                Ok(PairWriter::Csv(Box::new(writer), options.base64))
+            }
+        }
+    }
+
+    fn write(&mut self, kv: &KeyValuePair) -> Result<(), Error> {
+        match self {
+            PairWriter::JsonLines(writer, base64) => {
+                let mut row = json!({
+                    "key": encode(&kv.key, *base64),
+                    "value": encode(&kv.value, *base64),
+                });
+                if let Some(expires_at) = kv.expires_at {
+                    row["expires_at"] = json!(expires_at);
+                }
+                writeln!(writer, "{}", row)?;
+            }
+            PairWriter::Csv(writer, base64) => {
+                let expires_at = kv.expires_at.map_or(String::new(), |at| at.to_string());
+                writer
+                    .write_record([
+                        encode(&kv.key, *base64),
+                        encode(&kv.value, *base64),
+                        expires_at,
+                    ])
+                    .map_err(|_| Error::UnexpectedError)?
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            PairWriter::JsonLines(writer, _) => writer.flush()?,
+            PairWriter::Csv(writer, _) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+fn encode(s: &str, base64: bool) -> String {
+    match base64 {
+        true => STANDARD.encode(s),
+        false => s.to_string(),
+    }
+}
+
+fn decode(s: &str, base64: bool) -> Result<String, String> {
+    if !base64 {
+        return Ok(s.to_string());
+    }
+    let bytes = STANDARD
+        .decode(s)
+        .map_err(|e| format!("invalid base64: {}", e))?;
+    String::from_utf8(bytes).map_err(|_| "not valid UTF-8".to_string())
+}
+
+fn parse_json(line: &str, base64: bool) -> Result<KeyValuePair, String> {
+    let row: Value = serde_json::from_str(line).map_err(|e| format!("invalid JSON: {}", e))?;
+    let field = |name: &str| -> Result<String, String> {
+        let value = row
+            .get(name)
+            .and_then(Value::as_str)
+            .ok_or(format!("missing string field: {}", name))?;
+        decode(value, base64)
+    };
+    let mut kv = KeyValuePair::new(field("key")?, field("value")?);
+    kv.expires_at = match row.get("expires_at") {
+        None | Some(Value::Null) => None,
+        Some(expires_at) => Some(expires_at.as_u64().ok_or("invalid expires_at")?),
+    };
+    Ok(kv)
+}
+
+/// parse_csv parses a `key,value` record, or a `key,value,expires_at` one.
+fn parse_csv(record: &csv::StringRecord, base64: bool) -> Result<KeyValuePair, String> {
+    match (record.get(0), record.get(1), record.len()) {
+        (Some(key), Some(value), 2 | 3) => {
+            let mut kv = KeyValuePair::new(decode(key, base64)?, decode(value, base64)?);
+            kv.expires_at = match record.get(2) {
+                None | Some("") => None,
+                Some(expires_at) => Some(
+                    expires_at
+                        .parse()
+                        .map_err(|_| "invalid expires_at".to_string())?,
+                ),
+            };
+            Ok(kv)
+        }
+        _ => Err(format!("expected 2 or 3 fields, found {}", record.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn dump_and_load_round_trip() -> Result<(), Error> {
+        use crate::btree::{BTree, BTreeBuilder};
+        use crate::dump::{DumpOptions, Format};
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        fn new_tree() -> Result<BTree, Error> {
+            BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(2)
+                .build()
+        }
+
+        let mut btree = new_tree()?;
+        for (key, value) in [("c", "a,\"b\""), ("a", "x\ny"), ("b", "\u{1}\t")] {
+            btree.insert(KeyValuePair::new(key.to_string(), value.to_string()))?;
+        }
+        // A pair keeps its expiry, up to the highest one, while an expired pair is not dumped.
+        btree.insert(KeyValuePair::with_expiry(
+            "d".to_string(),
+            "ttl".to_string(),
+            u64::MAX,
+        ))?;
+        btree.insert(KeyValuePair::with_expiry(
+            "e".to_string(),
+            "gone".to_string(),
+            1,
+        ))?;
+        let expected = btree.scan(..)?;
+        assert_eq!(expected[3].expires_at, Some(u64::MAX));
+
+        for format in [Format::JsonLines, Format::Csv] {
+            for base64 in [false, true] {
+                let options = DumpOptions { format, base64 };
+                let mut out = Vec::new();
+                assert_eq!(btree.dump(&mut out, &options)?, 4);
+
+                let mut loaded = new_tree()?;
+                let report = loaded.load(out.as_slice(), &options)?;
+                assert_eq!(report.loaded, 4);
+                assert!(report.rejected.is_empty());
+                assert_eq!(loaded.scan(..)?, expected);
+                // The pairs are published with a single root following the initial one.
+                assert_eq!(loaded.roots()?.len(), 2);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn load_reports_rejected_rows() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::dump::{DumpOptions, Format};
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        let input = "\
+{\"key\": \"a\", \"value\": \"1\"}
+not json
+{\"key\": \"a\", \"value\": \"2\"}
+
+{\"key\": \"much too long a key\", \"value\": \"3\"}
+{\"value\": \"4\"}
+{\"key\": \"b\", \"value\": \"5\"}
+{\"key\": \"f\", \"value\": \"7\", \"expires_at\": \"soon\"}
+";
+        let report = btree.load(input.as_bytes(), &DumpOptions::default())?;
+        assert_eq!(report.loaded, 2);
+        let lines: Vec<usize> = report.rejected.iter().map(|r| r.line).collect();
+        assert_eq!(lines, vec![2, 3, 5, 6, 8]);
+
+        // Keys already in the tree are rejected as well.
+        let input = "key,value\nc,6\nd\n\"e\",\"not base64\"\na,8\n";
+        let options = DumpOptions {
+            format: Format::Csv,
+            base64: false,
+        };
+        let roots = btree.roots()?.len();
+        let report = btree.load(input.as_bytes(), &options)?;
+        assert_eq!(report.loaded, 2);
+        let lines: Vec<usize> = report.rejected.iter().map(|r| r.line).collect();
+        assert_eq!(lines, vec![3, 5]);
+        assert_eq!(btree.roots()?.len(), roots + 1);
+        assert_eq!(btree.search("a".to_string())?.value, "1");
+        assert_eq!(btree.search("e".to_string())?.value, "not base64");
+        assert!(btree.check()?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node::Node;
+use crate::node_type::{drop_expired, KeyValuePair, NodeType};
+
+/// Entry is the place of a key in the tree, either holding a pair (Occupied) or not (Vacant),
+/// as returned by BTree::entry. Both keep the path to the leaf of the key so that reading
+/// and then modifying the key takes a single descent from the root.
+/// An expired key is vacant, and modifying an entry drops the expired pairs of its leaf.
+pub enum Entry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>),
+}
+
+/// OccupiedEntry is the place of a key present in the tree.
+pub struct OccupiedEntry<'a> {
+    btree: &'a mut BTree,
+    /// Internal nodes on the path to the leaf, along with the index of the child descended to.
+    path: Vec<(Node, usize)>,
+    /// Pairs of the leaf, which is the root if the path is empty.
+    pairs: Vec<KeyValuePair>,
+    /// Index of the pair within the leaf.
+    idx: usize,
+}
+
+/// VacantEntry is the place a missing key would be inserted at.
+pub struct VacantEntry<'a> {
+    btree: &'a mut BTree,
+    path: Vec<(Node, usize)>,
+    pairs: Vec<KeyValuePair>,
+    key: String,
+    /// Index the pair would be inserted at within the leaf.
+    idx: usize,
+}
+
+impl BTree {
+    /// entry returns the place of a key in the tree for in-place reads and modifications.
+    pub fn entry(&mut self, key: String) -> Result<Entry<'_>, Error> {
+        let mut path = Vec::new();
+        let mut pairs = match self.find_leaf(&key, &mut path)?.node_type {
+            NodeType::Leaf(pairs) => pairs,
+            _ => return Err(Error::UnexpectedError),
+        };
+        drop_expired(&mut pairs);
+        Ok(match pairs.binary_search_by(|pair| pair.key.cmp(&key)) {
+            Ok(idx) => Entry::Occupied(OccupiedEntry {
+                btree: self,
+                path,
+                pairs,
+                idx,
+            }),
+            Err(idx) => Entry::Vacant(VacantEntry {
+                btree: self,
+                path,
+                pairs,
+                key,
+                idx,
+            }),
+        })
+    }
+}
+
+impl<'a> Entry<'a> {
+    /// key returns the key of the entry.
+    pub fn key(&self) -> &str {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// or_insert inserts the default value if the key is missing, and returns the value of the key.
+    pub fn or_insert(self, default: String) -> Result<String, Error> {
+        self.or_insert_with(|| default)
+    }
+
+    /// or_insert_with inserts the result of the default function if the key is missing,
+    /// and returns the value of the key.
+    pub fn or_insert_with<F: FnOnce() -> String>(self, default: F) -> Result<String, Error> {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.get().to_string()),
+            Entry::Vacant(entry) => {
+                let value = default();
+                entry.insert(value.clone())?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// and_modify applies the given function to the value of a present key
+    /// and writes the result back, returning the entry for further use.
+    pub fn and_modify<F: FnOnce(&mut String)>(self, f: F) -> Result<Self, Error> {
+        match self {
+            Entry::Occupied(mut entry) => {
+                let mut value = entry.get().to_string();
+                f(&mut value);
+                entry.insert(value)?;
+                Ok(Entry::Occupied(entry))
+            }
+            Entry::Vacant(entry) => Ok(Entry::Vacant(entry)),
+        }
+    }
+}
+
+impl<'a> OccupiedEntry<'a> {
+    /// key returns the key of the entry.
+    pub fn key(&self) -> &str {
+        &self.pairs[self.idx].key
+    }
+
+    /// get returns the value of the key.
+    pub fn get(&self) -> &str {
+        &self.pairs[self.idx].value
+    }
+
+    /// insert sets the value of the key, returning the previous one.
+    pub fn insert(&mut self, value: String) -> Result<String, Error> {
+        let old = std::mem::replace(&mut self.pairs[self.idx].value, value);
+        // The entry holds the tree exclusively and keeps every change made through it in its pairs,
+        // so publishing them along the (old copies of the) nodes it descended again is still valid.
+        let leaf = new_leaf(&self.path, self.pairs.clone());
+        if let Err(err) = self.btree.publish_path(self.path.clone(), leaf) {
+            self.pairs[self.idx].value = old;
+            return Err(err);
+        }
+        Ok(old)
+    }
+
+    /// remove deletes the key from the tree, returning its value.
+    pub fn remove(self) -> Result<String, Error> {
+        let mut pairs = self.pairs;
+        let kv = pairs.remove(self.idx);
+        let leaf = new_leaf(&self.path, pairs);
+        self.btree.publish_path(self.path, leaf)?;
+        Ok(kv.value)
+    }
+}
+
+impl<'a> VacantEntry<'a> {
+    /// key returns the key of the entry.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// insert adds the key to the tree with the given value.
+    pub fn insert(self, value: String) -> Result<(), Error> {
+        let mut pairs = self.pairs;
+        pairs.insert(self.idx, KeyValuePair::new(self.key, value));
+        let leaf = new_leaf(&self.path, pairs);
+        self.btree.publish_path(self.path, leaf)
+    }
+}
+
+/// new_leaf returns a leaf holding the given pairs at the end of the path.
+fn new_leaf(path: &[(Node, usize)], pairs: Vec<KeyValuePair>) -> Node {
+    Node::new(NodeType::Leaf(pairs), path.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn entry_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::entry::Entry;
+        use crate::storage::MemoryStorage;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        use std::collections::BTreeMap;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        let mut expected = BTreeMap::new();
+        let mut rng = StdRng::seed_from_u64(48);
+        for _ in 0..500 {
+            let key = format!("{:02}", rng.gen_range(0..60));
+            match rng.gen_range(0..3) {
+                // Count the occurrences of the key.
+                0 => {
+                    let value = btree
+                        .entry(key.clone())?
+                        .and_modify(|value| {
+                            *value = (value.parse::<u32>().unwrap() + 1).to_string()
+                        })?
+                        .or_insert("1".to_string())?;
+                    let count = expected.entry(key).or_insert(0);
+                    *count += 1;
+                    assert_eq!(value, count.to_string());
+                }
+                1 => match btree.entry(key.clone())? {
+                    Entry::Occupied(entry) => {
+                        assert_eq!(entry.key(), key);
+                        let value = entry.remove()?;
+                        assert_eq!(expected.remove(&key), Some(value.parse().unwrap()));
+                    }
+                    Entry::Vacant(entry) => {
+                        assert_eq!(entry.key(), key);
+                        assert!(!expected.contains_key(&key));
+                    }
+                },
+                _ => match btree.entry(key.clone())? {
+                    Entry::Occupied(mut entry) => {
+                        let old = entry.insert("10".to_string())?;
+                        assert_eq!(entry.get(), "10");
+                        assert_eq!(expected.insert(key, 10), Some(old.parse().unwrap()));
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert("10".to_string())?;
+                        assert_eq!(expected.insert(key, 10), None);
+                    }
+                },
+            }
+        }
+        assert!(btree.check()?.is_empty());
+        let pairs: Vec<(String, u32)> = btree
+            .scan(..)?
+            .into_iter()
+            .map(|kv| (kv.key, kv.value.parse().unwrap()))
+            .collect();
+        assert_eq!(pairs, expected.into_iter().collect::<Vec<_>>());
+
+        // A single entry can be modified several times.
+        if let Entry::Occupied(mut entry) = btree.entry(pairs[0].0.clone())? {
+            entry.insert("a".to_string())?;
+            entry.insert("b".to_string())?;
+            assert_eq!(entry.remove()?, "b");
+        }
+        assert_eq!(btree.len()?, pairs.len() - 1);
+        assert!(btree.check()?.is_empty());
+        Ok(())
+    }
+}
@@ -2,11 +2,28 @@
 pub enum Error {
     KeyNotFound,
     KeyAlreadyExists,
+    /// A key given to a bulk operation is smaller than the key preceding it.
+    KeyOutOfOrder,
+    /// A tree is expected to be empty, e.g. before it is bulk loaded.
+    TreeNotEmpty,
     UnexpectedError,
+    /// A tree is opened with a b parameter other than the given one it was built with.
+    BParameterMismatch {
+        b: usize,
+    },
+    /// A tree file holds pages while its write-ahead-log holds no root to recover them from,
+    /// e.g. the log was lost.
+    RootNotFound,
     KeyOverflowError,
     ValueOverflowError,
     TryFromSliceError(&'static str),
     UTF8Error,
+    /// A merge operator cannot combine a value with an operand, or no merge operator is set.
+    MergeError,
+    /// The page at the given offset does not match its checksum or holds an invalid node.
+    Corruption {
+        offset: usize,
+    },
 }
 
 impl std::convert::From<std::io::Error> for Error {
@@ -0,0 +1,377 @@
+use crate::error::Error;
+use crate::node_type::Offset;
+use crate::page::Page;
+use crate::page_layout::PAGE_SIZE;
+use crate::storage::{LogStorage, PageStorage};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Size of the unit a disk is assumed to write atomically.
+const SECTOR_SIZE: usize = 512;
+
+/// Fault is the failure injected into a write.
+#[derive(Clone, Copy, Debug)]
+pub enum Fault {
+    /// The write fails without reaching the disk.
+    Fail,
+    /// Only the first given number of bytes of the write reach the disk.
+    Truncate(usize),
+    /// Only every other sector of the write reaches the disk, the rest keep their old content.
+    Tear,
+}
+
+/// Disk keeps the content of a single storage as seen by the process (volatile)
+/// and as it would be found after a power loss (durable).
+#[derive(Default)]
+struct Disk {
+    volatile: Vec<u8>,
+    durable: Vec<u8>,
+}
+
+impl Disk {
+    fn write(&mut self, offset: usize, bytes: &[u8], fault: Option<Fault>) {
+        let len = self.volatile.len();
+        if len < offset + bytes.len() {
+            self.volatile.resize(offset + bytes.len(), 0x00);
+        }
+        match fault {
+            None => self.volatile[offset..offset + bytes.len()].clone_from_slice(bytes),
+            Some(Fault::Fail) => self.volatile.truncate(len.max(offset)),
+            Some(Fault::Truncate(size)) => {
+                let size = size.min(bytes.len());
+                self.volatile[offset..offset + size].clone_from_slice(&bytes[..size]);
+                self.volatile.truncate(len.max(offset + size));
+            }
+            Some(Fault::Tear) => {
+                for (idx, sector) in bytes.chunks(SECTOR_SIZE).enumerate().step_by(2) {
+                    let start = offset + idx * SECTOR_SIZE;
+                    self.volatile[start..start + sector.len()].clone_from_slice(sector);
+                }
+            }
+        }
+    }
+
+    fn sync(&mut self) {
+        self.durable = self.volatile.clone();
+    }
+}
+
+/// State is shared between the page and log storages of a FaultyDisk,
+/// so that writes are counted across both of them.
+#[derive(Default)]
+struct State {
+    pages: Disk,
+    log: Disk,
+    writes: usize,
+    fault_at: Option<(usize, Fault)>,
+    crashed: bool,
+}
+
+impl State {
+    /// next_write counts a write, returning the fault to inject into it (if any).
+    fn next_write(&mut self) -> Result<Option<Fault>, Error> {
+        if self.crashed {
+            return Err(Error::UnexpectedError);
+        }
+        self.writes += 1;
+        match self.fault_at {
+            Some((write, fault)) if write == self.writes => {
+                self.crashed = true;
+                Ok(Some(fault))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// FaultyDisk is an in-memory disk backing both the pages and the write-ahead-log of a BTree,
+/// which can inject a fault into the Nth write and simulate a power loss.
+#[derive(Clone, Default)]
+pub struct FaultyDisk {
+    state: Rc<RefCell<State>>,
+}
+
+impl FaultyDisk {
+    pub fn new() -> FaultyDisk {
+        FaultyDisk::default()
+    }
+
+    /// fail_at injects the fault into the given write (counting from one),
+    /// every write following it fails as if the process died.
+    pub fn fail_at(&self, write: usize, fault: Fault) {
+        self.state.borrow_mut().fault_at = Some((write, fault));
+    }
+
+    /// writes returns the number of writes issued so far.
+    pub fn writes(&self) -> usize {
+        self.state.borrow().writes
+    }
+
+    /// crash simulates the process dying, if drop_unsynced is set writes
+    /// that were not synced are lost as well (i.e. a power loss).
+    pub fn crash(&self, drop_unsynced: bool) {
+        let mut state = self.state.borrow_mut();
+        if drop_unsynced {
+            state.pages.volatile = state.pages.durable.clone();
+            state.log.volatile = state.log.durable.clone();
+        }
+        state.writes = 0;
+        state.fault_at = None;
+        state.crashed = false;
+    }
+
+    pub fn pages(&self) -> FaultyPageStorage {
+        FaultyPageStorage {
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn log(&self) -> FaultyLogStorage {
+        FaultyLogStorage {
+            state: self.state.clone(),
+        }
+    }
+}
+
+pub struct FaultyPageStorage {
+    state: Rc<RefCell<State>>,
+}
+
+impl PageStorage for FaultyPageStorage {
+    fn read_page(&mut self, offset: &Offset) -> Result<Page, Error> {
+        let state = self.state.borrow();
+        let bytes = state
+            .pages
+            .volatile
+            .get(offset.0..offset.0 + PAGE_SIZE)
+            .ok_or(Error::UnexpectedError)?;
+        let mut page: [u8; PAGE_SIZE] = [0x00; PAGE_SIZE];
+        page.clone_from_slice(bytes);
+        Ok(Page::new(page))
+    }
+
+    fn write_page(&mut self, page: &Page, offset: &Offset) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        let fault = state.next_write()?;
+        state.pages.write(offset.0, &page.get_data(), fault);
+        match fault {
+            Some(_) => Err(Error::UnexpectedError),
+            None => Ok(()),
+        }
+    }
+
+    fn allocate_page(&mut self) -> Result<Offset, Error> {
+        let mut state = self.state.borrow_mut();
+        if state.crashed {
+            return Err(Error::UnexpectedError);
+        }
+        let res = Offset(state.pages.volatile.len());
+        state.pages.volatile.resize(res.0 + PAGE_SIZE, 0x00);
+        Ok(res)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        if state.crashed {
+            return Err(Error::UnexpectedError);
+        }
+        state.pages.sync();
+        Ok(())
+    }
+
+    fn size(&mut self) -> Result<usize, Error> {
+        Ok(self.state.borrow().pages.volatile.len())
+    }
+}
+
+pub struct FaultyLogStorage {
+    state: Rc<RefCell<State>>,
+}
+
+impl LogStorage for FaultyLogStorage {
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        let fault = state.next_write()?;
+        let offset = state.log.volatile.len();
+        state.log.write(offset, bytes, fault);
+        match fault {
+            Some(_) => Err(Error::UnexpectedError),
+            None => Ok(()),
+        }
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let state = self.state.borrow();
+        let bytes = state
+            .log
+            .volatile
+            .get(offset..offset + buf.len())
+            .ok_or(Error::UnexpectedError)?;
+        buf.clone_from_slice(bytes);
+        Ok(())
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        Ok(self.state.borrow().log.volatile.len())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error> {
+        self.state.borrow_mut().log.volatile.truncate(len);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        if state.crashed {
+            return Err(Error::UnexpectedError);
+        }
+        state.log.sync();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::btree::{BTree, BTreeBuilder};
+    use crate::error::Error;
+    use crate::fault::{Fault, FaultyDisk};
+    use crate::node_type::{Key, KeyValuePair};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    const B: usize = 2;
+    const KEYS: usize = 24;
+
+    enum Op {
+        Insert(String, String),
+        Delete(String),
+    }
+
+    /// workload generates random operations, which are all valid given the previous ones,
+    /// alongside the expected content of the tree after each prefix of them.
+    fn workload(seed: u64, len: usize) -> (Vec<Op>, Vec<BTreeMap<String, String>>) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut model = BTreeMap::new();
+        let mut ops = Vec::new();
+        let mut states = vec![model.clone()];
+        for i in 0..len {
+            let key = format!("k{:02}", rng.gen_range(0..KEYS));
+            if model.contains_key(&key) {
+                model.remove(&key);
+                ops.push(Op::Delete(key));
+            } else {
+                model.insert(key.clone(), format!("v{}", i));
+                ops.push(Op::Insert(key.clone(), format!("v{}", i)));
+            }
+            states.push(model.clone());
+        }
+        (ops, states)
+    }
+
+    /// run applies the operations until one of them fails, returning the number of successful ones.
+    fn run(disk: &FaultyDisk, ops: &[Op]) -> usize {
+        let mut btree = match open(disk) {
+            Ok(btree) => btree,
+            Err(_) => return 0,
+        };
+        for (done, op) in ops.iter().enumerate() {
+            let res = match op {
+                Op::Insert(key, value) => {
+                    btree.insert(KeyValuePair::new(key.clone(), value.clone()))
+                }
+                Op::Delete(key) => btree.delete(Key(key.clone())),
+            };
+            if res.is_err() {
+                return done;
+            }
+        }
+        ops.len()
+    }
+
+    fn open(disk: &FaultyDisk) -> Result<BTree, Error> {
+        BTreeBuilder::new()
+            .storage(disk.pages(), disk.log())
+            .b_parameter(B)
+            .build()
+    }
+
+    fn contents(btree: &mut BTree) -> Result<BTreeMap<String, String>, Error> {
+        let mut res = BTreeMap::new();
+        for i in 0..KEYS {
+            match btree.search(format!("k{:02}", i)) {
+                Ok(kv) => {
+                    res.insert(kv.key, kv.value);
+                }
+                Err(Error::KeyNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(res)
+    }
+
+    #[test]
+    fn recovers_after_crash_at_every_write() -> Result<(), Error> {
+        let (ops, states) = workload(7, 40);
+        let clean = FaultyDisk::new();
+        assert_eq!(run(&clean, &ops), ops.len());
+        let total_writes = clean.writes();
+
+        for write in 1..=total_writes {
+            for fault in [Fault::Fail, Fault::Truncate(5), Fault::Tear] {
+                for drop_unsynced in [true, false] {
+                    let disk = FaultyDisk::new();
+                    disk.fail_at(write, fault);
+                    let done = run(&disk, &ops);
+                    disk.crash(drop_unsynced);
+
+                    // Every operation that returned successfully was committed,
+                    // the one that failed may or may not have been.
+                    let mut btree = open(&disk)?;
+                    assert!(
+                        btree.check()?.is_empty(),
+                        "write {} with {:?}",
+                        write,
+                        fault
+                    );
+                    let recovered = contents(&mut btree)?;
+                    let scanned: BTreeMap<String, String> = btree
+                        .scan(..)?
+                        .into_iter()
+                        .map(|kv| (kv.key, kv.value))
+                        .collect();
+                    assert_eq!(scanned, recovered);
+                    // The counts kept in internal nodes agree with the pairs recovered.
+                    assert_eq!(btree.len()?, recovered.len());
+                    let committed = &states[done..(done + 2).min(states.len())];
+                    assert!(
+                        committed.contains(&recovered),
+                        "write {} with {:?} (drop unsynced: {}) recovered {:?}",
+                        write,
+                        fault,
+                        drop_unsynced,
+                        recovered
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn recovered_tree_accepts_writes() -> Result<(), Error> {
+        let (ops, _) = workload(11, 20);
+        let disk = FaultyDisk::new();
+        disk.fail_at(30, Fault::Truncate(3));
+        run(&disk, &ops);
+        disk.crash(false);
+
+        let mut btree = open(&disk)?;
+        btree.insert(KeyValuePair::new("new".to_string(), "value".to_string()))?;
+        disk.crash(true);
+
+        let mut btree = open(&disk)?;
+        assert_eq!(btree.search("new".to_string())?.value, "value");
+        Ok(())
+    }
+}
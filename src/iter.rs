@@ -0,0 +1,196 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{now, Key, KeyValuePair, NodeType, Offset};
+use std::ops::{Bound, RangeBounds};
+use std::vec;
+
+/// Range iterates over the key-value pairs of a tree within a range of keys, in key order.
+///
+/// Leaves do not link to their siblings: under copy-on-write a sibling pointer would force
+/// every write to copy the neighbours of the modified leaf (and their neighbours in turn).
+/// Instead the iterator keeps the position within each internal node on the path to the
+/// current leaf, so moving to the next leaf only reads the nodes which differ between the two paths.
+/// Pairs which expired by the time the iterator was created are skipped.
+pub struct Range<'a> {
+    btree: &'a mut BTree,
+    end: Bound<String>,
+    now: u64,
+    /// Children of the internal nodes on the path to the current leaf,
+    /// along with the index of the next child to visit.
+    stack: Vec<(Vec<Offset>, usize)>,
+    /// Pairs of the current leaf yet to be returned.
+    pairs: vec::IntoIter<KeyValuePair>,
+    done: bool,
+}
+
+impl<'a> Range<'a> {
+    fn new(btree: &'a mut BTree, start: Bound<String>, end: Bound<String>) -> Result<Self, Error> {
+        let root_offset = btree.wal.get_root()?;
+        let mut res = Range {
+            btree,
+            end,
+            now: now(),
+            stack: Vec::new(),
+            pairs: Vec::new().into_iter(),
+            done: false,
+        };
+        res.seek(root_offset, &start)?;
+        Ok(res)
+    }
+
+    /// seek descends from the node at the given offset to the leaf holding the first key
+    /// within the start bound, skipping the pairs of the leaf below it.
+    fn seek(&mut self, offset: Offset, start: &Bound<String>) -> Result<(), Error> {
+        let mut offset = offset;
+        loop {
+            match self.read(&offset)? {
+                NodeType::Internal(children, keys, _) => {
+                    // The child at idx holds the keys within (keys[idx - 1], keys[idx]].
+                    let idx = match start {
+                        Bound::Included(start) => keys.partition_point(|Key(key)| key < start),
+                        Bound::Excluded(start) => keys.partition_point(|Key(key)| key <= start),
+                        Bound::Unbounded => 0,
+                    };
+                    offset = children.get(idx).ok_or(Error::UnexpectedError)?.clone();
+                    self.stack.push((children, idx + 1));
+                }
+                NodeType::Leaf(mut pairs) => {
+                    let idx = match start {
+                        Bound::Included(start) => pairs.partition_point(|kv| kv.key < *start),
+                        Bound::Excluded(start) => pairs.partition_point(|kv| kv.key <= *start),
+                        Bound::Unbounded => 0,
+                    };
+                    pairs.drain(..idx);
+                    self.pairs = pairs.into_iter();
+                    return Ok(());
+                }
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+        }
+    }
+
+    /// next_leaf moves to the leftmost leaf following the current one,
+    /// returning false if the current leaf is the last one.
+    fn next_leaf(&mut self) -> Result<bool, Error> {
+        while let Some((children, idx)) = self.stack.last_mut() {
+            if let Some(offset) = children.get(*idx).cloned() {
+                *idx += 1;
+                self.seek(offset, &Bound::Unbounded)?;
+                return Ok(true);
+            }
+            self.stack.pop();
+        }
+        Ok(false)
+    }
+
+    fn read(&mut self, offset: &Offset) -> Result<NodeType, Error> {
+        Ok(self.btree.pager.get_node(offset)?.node_type)
+    }
+
+    fn next_pair(&mut self) -> Result<Option<KeyValuePair>, Error> {
+        loop {
+            if let Some(kv) = self.pairs.next() {
+                let within_end = match &self.end {
+                    Bound::Included(end) => kv.key <= *end,
+                    Bound::Excluded(end) => kv.key < *end,
+                    Bound::Unbounded => true,
+                };
+                if within_end && kv.is_expired(self.now) {
+                    continue;
+                }
+                return Ok(Some(kv).filter(|_| within_end));
+            }
+            if !self.next_leaf()? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+impl Iterator for Range<'_> {
+    type Item = Result<KeyValuePair, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.next_pair().transpose();
+        // Stop after the last pair within range as well as after an error.
+        if !matches!(res, Some(Ok(_))) {
+            self.done = true;
+        }
+        res
+    }
+}
+
+impl BTree {
+    /// range returns an iterator over the key-value pairs whose key lies within the given range,
+    /// in key order. Leaves are read as the iterator advances.
+    pub fn range<R: RangeBounds<String>>(&mut self, range: R) -> Result<Range<'_>, Error> {
+        Range::new(
+            self,
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+        )
+    }
+
+    /// iter returns an iterator over every key-value pair of the tree in key order.
+    pub fn iter(&mut self) -> Result<Range<'_>, Error> {
+        self.range(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn range_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+        use std::ops::Bound;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        assert_eq!(btree.iter()?.count(), 0);
+        let keys: Vec<String> = (0..60).map(|i| format!("{:03}", i * 2)).collect();
+        for key in &keys {
+            btree.insert(KeyValuePair::new(key.clone(), key.clone()))?;
+        }
+
+        let all = btree.iter()?.collect::<Result<Vec<_>, _>>()?;
+        let all_keys: Vec<String> = all.into_iter().map(|kv| kv.key).collect();
+        assert_eq!(all_keys, keys);
+
+        // Bounds both on and between the keys of the tree.
+        let range_keys = |btree: &mut crate::btree::BTree,
+                          start: Bound<&str>,
+                          end: Bound<&str>|
+         -> Result<Vec<String>, Error> {
+            let range = (start.map(str::to_string), end.map(str::to_string));
+            btree.range(range)?.map(|kv| kv.map(|kv| kv.key)).collect()
+        };
+        assert_eq!(
+            range_keys(&mut btree, Bound::Included("010"), Bound::Excluded("016"))?,
+            vec!["010", "012", "014"]
+        );
+        assert_eq!(
+            range_keys(&mut btree, Bound::Excluded("010"), Bound::Included("016"))?,
+            vec!["012", "014", "016"]
+        );
+        assert_eq!(
+            range_keys(&mut btree, Bound::Included("009"), Bound::Excluded("013"))?,
+            vec!["010", "012"]
+        );
+        assert_eq!(
+            range_keys(&mut btree, Bound::Excluded("114"), Bound::Unbounded)?,
+            vec!["116", "118"]
+        );
+        assert!(range_keys(&mut btree, Bound::Included("5"), Bound::Unbounded)?.is_empty());
+        assert!(range_keys(&mut btree, Bound::Included("012"), Bound::Excluded("012"))?.is_empty());
+        Ok(())
+    }
+}
@@ -1,8 +1,23 @@
 pub mod btree;
+pub mod bulk;
+pub mod check;
+pub mod cursor;
+pub mod dot;
+pub mod dump;
+pub mod entry;
 pub mod error;
+#[cfg(test)]
+mod fault;
+pub mod iter;
+pub mod merge;
 pub mod node;
 pub mod node_type;
+pub mod order;
 pub mod page;
 mod page_layout;
 mod pager;
+pub mod prefix;
+pub mod print;
+pub mod stats;
+pub mod storage;
 mod wal;
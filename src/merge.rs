@@ -0,0 +1,154 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{drop_expired, KeyValuePair, NodeType};
+
+/// MergeOperator combines the value of a key (None if the key is missing) with an operand
+/// into the new value of the key, as set on the builder and applied by BTree::merge.
+pub trait MergeOperator {
+    fn merge(&self, key: &str, value: Option<&str>, operand: &str) -> Result<String, Error>;
+}
+
+/// Any function of the key, the value and the operand is a merge operator.
+impl<F> MergeOperator for F
+where
+    F: Fn(&str, Option<&str>, &str) -> Result<String, Error>,
+{
+    fn merge(&self, key: &str, value: Option<&str>, operand: &str) -> Result<String, Error> {
+        self(key, value, operand)
+    }
+}
+
+/// AddU64 treats values and operands as decimal u64 counters (a missing key counts as zero)
+/// and adds the operand to the value, failing on overflow.
+pub struct AddU64;
+
+impl MergeOperator for AddU64 {
+    fn merge(&self, _key: &str, value: Option<&str>, operand: &str) -> Result<String, Error> {
+        let value = parse_u64(value.unwrap_or("0"))?;
+        let sum = value
+            .checked_add(parse_u64(operand)?)
+            .ok_or(Error::MergeError)?;
+        Ok(sum.to_string())
+    }
+}
+
+/// MaxU64 treats values and operands as decimal u64 numbers and keeps the largest of them.
+pub struct MaxU64;
+
+impl MergeOperator for MaxU64 {
+    fn merge(&self, _key: &str, value: Option<&str>, operand: &str) -> Result<String, Error> {
+        let operand = parse_u64(operand)?;
+        let max = match value {
+            Some(value) => parse_u64(value)?.max(operand),
+            None => operand,
+        };
+        Ok(max.to_string())
+    }
+}
+
+/// Append appends the operand to the value, so a list is kept by appending delimited items.
+pub struct Append;
+
+impl MergeOperator for Append {
+    fn merge(&self, _key: &str, value: Option<&str>, operand: &str) -> Result<String, Error> {
+        Ok(format!("{}{}", value.unwrap_or(""), operand))
+    }
+}
+
+fn parse_u64(value: &str) -> Result<u64, Error> {
+    value.parse().map_err(|_| Error::MergeError)
+}
+
+impl BTree {
+    /// merge combines the value of a key with the given operand using the merge operator of the tree,
+    /// inserting the key if it is missing (or expired). The value is read from the leaf copied on the way to
+    /// write the new value, so no separate search is needed.
+    pub fn merge(&mut self, key: &str, operand: &str) -> Result<(), Error> {
+        let mut path = Vec::new();
+        let mut leaf = self.find_leaf(key, &mut path)?;
+        let pairs = match &mut leaf.node_type {
+            NodeType::Leaf(pairs) => pairs,
+            _ => return Err(Error::UnexpectedError),
+        };
+        drop_expired(pairs);
+        let operator = self.merge_operator.as_ref().ok_or(Error::MergeError)?;
+        match pairs.binary_search_by(|pair| pair.key.as_str().cmp(key)) {
+            Ok(idx) => {
+                pairs[idx].value = operator.merge(key, Some(&pairs[idx].value), operand)?;
+            }
+            Err(idx) => {
+                let value = operator.merge(key, None, operand)?;
+                pairs.insert(idx, KeyValuePair::new(key.to_string(), value));
+            }
+        }
+        self.publish_path(path, leaf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn merge_works() -> Result<(), Error> {
+        use crate::btree::{BTree, BTreeBuilder};
+        use crate::merge::{AddU64, Append, MaxU64, MergeOperator};
+        use crate::storage::MemoryStorage;
+
+        fn new_tree(operator: impl MergeOperator + 'static) -> Result<BTree, Error> {
+            BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(2)
+                .merge_operator(operator)
+                .build()
+        }
+
+        let mut btree = new_tree(AddU64)?;
+        for i in 0..100 {
+            btree.merge(&format!("{:02}", i % 30), &i.to_string())?;
+        }
+        assert!(btree.check()?.is_empty());
+        assert_eq!(btree.len()?, 30);
+        for i in 0..30 {
+            let expected: u64 = (i..100).step_by(30).sum();
+            assert_eq!(
+                btree.search(format!("{:02}", i))?.value,
+                expected.to_string()
+            );
+        }
+        assert!(matches!(btree.merge("00", "x"), Err(Error::MergeError)));
+        let max = u64::MAX.to_string();
+        assert!(matches!(
+            AddU64.merge("k", Some(&max), "1"),
+            Err(Error::MergeError)
+        ));
+
+        let mut btree = new_tree(MaxU64)?;
+        for value in ["3", "7", "5"] {
+            btree.merge("k", value)?;
+        }
+        assert_eq!(btree.search("k".to_string())?.value, "7");
+
+        let mut btree = new_tree(Append)?;
+        for item in ["a,", "b,", "c,"] {
+            btree.merge("list", item)?;
+        }
+        assert_eq!(btree.search("list".to_string())?.value, "a,b,c,");
+
+        // Closures receive the key as well.
+        let mut btree = new_tree(|key: &str, value: Option<&str>, operand: &str| {
+            Ok(format!("{}={}{}", key, operand, value.map_or(0, str::len)))
+        })?;
+        btree.merge("k", "v")?;
+        btree.merge("k", "w")?;
+        assert_eq!(btree.search("k".to_string())?.value, "k=w4");
+
+        // Merging fails without an operator.
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        assert!(matches!(btree.merge("k", "1"), Err(Error::MergeError)));
+        Ok(())
+    }
+}
@@ -1,10 +1,10 @@
 use crate::error::Error;
-use crate::node_type::{Key, KeyValuePair, NodeType, Offset};
+use crate::node_type::{Count, Key, KeyValuePair, NodeType, Offset};
 use crate::page::Page;
 use crate::page_layout::{
-    FromByte, INTERNAL_NODE_HEADER_SIZE, INTERNAL_NODE_NUM_CHILDREN_OFFSET, IS_ROOT_OFFSET,
-    KEY_SIZE, LEAF_NODE_HEADER_SIZE, LEAF_NODE_NUM_PAIRS_OFFSET, NODE_TYPE_OFFSET,
-    PARENT_POINTER_OFFSET, PTR_SIZE, VALUE_SIZE,
+    FromByte, EXPIRY_SIZE, HAS_EXPIRY_SIZE, INTERNAL_NODE_HEADER_SIZE,
+    INTERNAL_NODE_NUM_CHILDREN_OFFSET, IS_ROOT_OFFSET, KEY_SIZE, LEAF_NODE_HEADER_SIZE,
+    LEAF_NODE_NUM_PAIRS_OFFSET, NODE_TYPE_OFFSET, PAGE_SIZE, PAIR_SIZE, PTR_SIZE, VALUE_SIZE,
 };
 use std::convert::TryFrom;
 use std::str;
@@ -14,17 +14,12 @@ use std::str;
 pub struct Node {
     pub node_type: NodeType,
     pub is_root: bool,
-    pub parent_offset: Option<Offset>,
 }
 
 // Node represents a node in the B-Tree.
 impl Node {
-    pub fn new(node_type: NodeType, is_root: bool, parent_offset: Option<Offset>) -> Node {
-        Node {
-            node_type,
-            is_root,
-            parent_offset,
-        }
+    pub fn new(node_type: NodeType, is_root: bool) -> Node {
+        Node { node_type, is_root }
     }
 
     /// split creates a sibling node from a given node by splitting the node in two around a median.
@@ -32,19 +27,19 @@ impl Node {
     /// while moving the set of [b, 2b-1] keys to the sibling.
     pub fn split(&mut self, b: usize) -> Result<(Key, Node), Error> {
         match self.node_type {
-            NodeType::Internal(ref mut children, ref mut keys) => {
+            NodeType::Internal(ref mut children, ref mut keys, ref mut counts) => {
                 // Populate siblings keys.
                 let mut sibling_keys = keys.split_off(b - 1);
                 // Pop median key - to be added to the parent..
                 let median_key = sibling_keys.remove(0);
-                // Populate siblings children.
+                // Populate siblings children along with their counts.
                 let sibling_children = children.split_off(b);
+                let sibling_counts = counts.split_off(b);
                 Ok((
                     median_key,
                     Node::new(
-                        NodeType::Internal(sibling_children, sibling_keys),
+                        NodeType::Internal(sibling_children, sibling_keys, sibling_counts),
                         false,
-                        self.parent_offset.clone(),
                     ),
                 ))
             }
@@ -56,11 +51,7 @@ impl Node {
 
                 Ok((
                     Key(median_pair.key),
-                    Node::new(
-                        NodeType::Leaf(sibling_pairs),
-                        false,
-                        self.parent_offset.clone(),
-                    ),
+                    Node::new(NodeType::Leaf(sibling_pairs), false),
                 ))
             }
             NodeType::Unexpected => Err(Error::UnexpectedError),
@@ -70,22 +61,25 @@ impl Node {
 
 /// Implement TryFrom<Page> for Node allowing for easier
 /// deserialization of data from a Page.
+/// A page holding counts which do not fit in it is reported as Corruption; the page does not
+/// know its own offset, so Pager::get_node fills it in.
 impl TryFrom<Page> for Node {
     type Error = Error;
     fn try_from(page: Page) -> Result<Node, Error> {
         let raw = page.get_data();
         let node_type = NodeType::from(raw[NODE_TYPE_OFFSET]);
         let is_root = raw[IS_ROOT_OFFSET].from_byte();
-        let parent_offset: Option<Offset>;
-        if is_root {
-            parent_offset = None;
-        } else {
-            parent_offset = Some(Offset(page.get_value_from_offset(PARENT_POINTER_OFFSET)?));
-        }
 
         match node_type {
-            NodeType::Internal(mut children, mut keys) => {
+            NodeType::Internal(mut children, mut keys, mut counts) => {
                 let num_children = page.get_value_from_offset(INTERNAL_NODE_NUM_CHILDREN_OFFSET)?;
+                // Refuse counts which do not fit in a page rather than reading past its end.
+                if num_children
+                    > (PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE)
+                        / (2 * PTR_SIZE + EXPIRY_SIZE + KEY_SIZE)
+                {
+                    return Err(Error::Corruption { offset: 0 });
+                }
                 let mut offset = INTERNAL_NODE_HEADER_SIZE;
                 for _i in 1..=num_children {
                     let child_offset = page.get_value_from_offset(offset)?;
@@ -93,6 +87,21 @@ impl TryFrom<Page> for Node {
                     offset += PTR_SIZE;
                 }
 
+                for _i in 1..=num_children {
+                    counts.push(Count {
+                        pairs: page.get_value_from_offset(offset)?,
+                        earliest_expiry: u64::MAX,
+                    });
+                    offset += PTR_SIZE;
+                }
+
+                for count in counts.iter_mut() {
+                    let mut expiry_raw = [0x00; EXPIRY_SIZE];
+                    expiry_raw.clone_from_slice(page.get_ptr_from_offset(offset, EXPIRY_SIZE));
+                    count.earliest_expiry = u64::from_be_bytes(expiry_raw);
+                    offset += EXPIRY_SIZE;
+                }
+
                 // Number of keys is always one less than the number of children (i.e. branching factor)
                 for _i in 1..num_children {
                     let key_raw = page.get_ptr_from_offset(offset, KEY_SIZE);
@@ -105,15 +114,17 @@ impl TryFrom<Page> for Node {
                     keys.push(Key(key.trim_matches(char::from(0)).to_string()));
                 }
                 Ok(Node::new(
-                    NodeType::Internal(children, keys),
+                    NodeType::Internal(children, keys, counts),
                     is_root,
-                    parent_offset,
                 ))
             }
 
             NodeType::Leaf(mut pairs) => {
                 let mut offset = LEAF_NODE_NUM_PAIRS_OFFSET;
                 let num_keys_val_pairs = page.get_value_from_offset(offset)?;
+                if num_keys_val_pairs > (PAGE_SIZE - LEAF_NODE_HEADER_SIZE) / PAIR_SIZE {
+                    return Err(Error::Corruption { offset: 0 });
+                }
                 offset = LEAF_NODE_HEADER_SIZE;
 
                 for _i in 0..num_keys_val_pairs {
@@ -131,13 +142,22 @@ impl TryFrom<Page> for Node {
                     };
                     offset += VALUE_SIZE;
 
+                    let has_expiry =
+                        page.get_ptr_from_offset(offset, HAS_EXPIRY_SIZE)[0].from_byte();
+                    offset += HAS_EXPIRY_SIZE;
+                    let mut expiry_raw = [0x00; EXPIRY_SIZE];
+                    expiry_raw.clone_from_slice(page.get_ptr_from_offset(offset, EXPIRY_SIZE));
+                    let expires_at = u64::from_be_bytes(expiry_raw);
+                    offset += EXPIRY_SIZE;
+
                     // Trim leading or trailing zeros.
-                    pairs.push(KeyValuePair::new(
-                        key.trim_matches(char::from(0)).to_string(),
-                        value.trim_matches(char::from(0)).to_string(),
-                    ))
+                    pairs.push(KeyValuePair {
+                        key: key.trim_matches(char::from(0)).to_string(),
+                        value: value.trim_matches(char::from(0)).to_string(),
+                        expires_at: Some(expires_at).filter(|_| has_expiry),
+                    })
                 }
-                Ok(Node::new(NodeType::Leaf(pairs), is_root, parent_offset))
+                Ok(Node::new(NodeType::Leaf(pairs), is_root))
             }
 
             NodeType::Unexpected => Err(Error::UnexpectedError),
@@ -150,28 +170,32 @@ impl TryFrom<Page> for Node {
 ///  Unit Tests. ///
 ///              ///
 ////////////////////
-
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::get_first)]
 mod tests {
     use crate::error::Error;
     use crate::node::{
-        Node, Page, INTERNAL_NODE_HEADER_SIZE, KEY_SIZE, LEAF_NODE_HEADER_SIZE, PTR_SIZE,
-        VALUE_SIZE,
+        Node, Page, EXPIRY_SIZE, HAS_EXPIRY_SIZE, INTERNAL_NODE_HEADER_SIZE, KEY_SIZE,
+        LEAF_NODE_HEADER_SIZE, PTR_SIZE, VALUE_SIZE,
     };
-    use crate::node_type::{Key, NodeType};
+    use crate::node_type::{Count, Key, KeyValuePair, NodeType};
     use crate::page_layout::PAGE_SIZE;
     use std::convert::TryFrom;
 
     #[test]
     fn page_to_node_works_for_leaf_node() -> Result<(), Error> {
-        const DATA_LEN: usize = LEAF_NODE_HEADER_SIZE + KEY_SIZE + VALUE_SIZE;
+        const DATA_LEN: usize =
+            LEAF_NODE_HEADER_SIZE + KEY_SIZE + VALUE_SIZE + HAS_EXPIRY_SIZE + EXPIRY_SIZE;
         let page_data: [u8; DATA_LEN] = [
             0x01, // Is-Root byte.
             0x02, // Leaf Node type byte.
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Parent offset.
+            0x00, 0x00, 0x00, 0x00, // Checksum.
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // Number of Key-Value pairs.
             0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x00, 0x00, 0x00, 0x00, 0x00, // "hello"
             0x77, 0x6f, 0x72, 0x6c, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00, // "world"
+            0x01, // Has an expiry.
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x51,
+            0x80, // Expires a day after the Unix epoch.
         ];
         let junk: [u8; PAGE_SIZE - DATA_LEN] = [0x00; PAGE_SIZE - DATA_LEN];
         let mut page = [0x00; PAGE_SIZE];
@@ -182,21 +206,37 @@ mod tests {
         let node = Node::try_from(Page::new(page))?;
 
         assert_eq!(node.is_root, true);
+        assert_eq!(
+            node.node_type,
+            NodeType::Leaf(vec![KeyValuePair::with_expiry(
+                "hello".to_string(),
+                "world".to_string(),
+                86400
+            )])
+        );
         Ok(())
     }
 
     #[test]
     fn page_to_node_works_for_internal_node() -> Result<(), Error> {
         use crate::node_type::Key;
-        const DATA_LEN: usize = INTERNAL_NODE_HEADER_SIZE + 3 * PTR_SIZE + 2 * KEY_SIZE;
+        const DATA_LEN: usize =
+            INTERNAL_NODE_HEADER_SIZE + 6 * PTR_SIZE + 3 * EXPIRY_SIZE + 2 * KEY_SIZE;
         let page_data: [u8; DATA_LEN] = [
             0x01, // Is-Root byte.
             0x01, // Internal Node type byte.
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Parent offset.
+            0x00, 0x00, 0x00, 0x00, // Checksum.
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, // Number of children.
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, // 4096  (2nd Page)
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, // 8192  (3rd Page)
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x00, // 12288 (4th Page)
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // Two pairs under the 1st child.
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x03, // Three pairs under the 2nd child.
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // Two pairs under the 3rd child.
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // No expiry under the 1st child.
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x51, 0x80, // Expires a day after the epoch.
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // No expiry under the 3rd child.
             0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x00, 0x00, 0x00, 0x00, 0x00, // "hello"
             0x77, 0x6f, 0x72, 0x6c, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00, // "world"
         ];
@@ -210,8 +250,16 @@ mod tests {
 
         let node = Node::try_from(Page::new(page))?;
 
-        if let NodeType::Internal(_, keys) = node.node_type {
+        if let NodeType::Internal(_, keys, counts) = node.node_type {
             assert_eq!(keys.len(), 2);
+            assert_eq!(
+                counts,
+                vec![
+                    Count::new(2, u64::MAX),
+                    Count::new(3, 86400),
+                    Count::new(2, u64::MAX)
+                ]
+            );
 
             let Key(first_key) = match keys.get(0) {
                 Some(key) => key,
@@ -241,7 +289,6 @@ mod tests {
                 KeyValuePair::new("ariana".to_string(), "grande".to_string()),
             ]),
             true,
-            None,
         );
 
         let (median, sibling) = node.split(2)?;
@@ -251,11 +298,13 @@ mod tests {
             NodeType::Leaf(vec![
                 KeyValuePair {
                     key: "foo".to_string(),
-                    value: "bar".to_string()
+                    value: "bar".to_string(),
+                    expires_at: None
                 },
                 KeyValuePair {
                     key: "lebron".to_string(),
-                    value: "james".to_string()
+                    value: "james".to_string(),
+                    expires_at: None
                 }
             ])
         );
@@ -288,9 +337,11 @@ mod tests {
                     Key("lebron".to_string()),
                     Key("ariana".to_string()),
                 ],
+                [1, 2, 3, 4]
+                    .map(|pairs| Count::new(pairs, u64::MAX))
+                    .to_vec(),
             ),
             true,
-            None,
         );
 
         let (median, sibling) = node.split(2)?;
@@ -299,14 +350,16 @@ mod tests {
             node.node_type,
             NodeType::Internal(
                 vec![Offset(PAGE_SIZE), Offset(PAGE_SIZE * 2)],
-                vec![Key("foo bar".to_string())]
+                vec![Key("foo bar".to_string())],
+                vec![Count::new(1, u64::MAX), Count::new(2, u64::MAX)]
             )
         );
         assert_eq!(
             sibling.node_type,
             NodeType::Internal(
                 vec![Offset(PAGE_SIZE * 3), Offset(PAGE_SIZE * 4)],
-                vec![Key("ariana".to_string())]
+                vec![Key("ariana".to_string())],
+                vec![Count::new(3, u64::MAX), Count::new(4, u64::MAX)]
             )
         );
         Ok(())
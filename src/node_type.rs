@@ -3,6 +3,9 @@ use crate::page_layout::PTR_SIZE;
 use std::cmp::{Eq, Ord, Ordering, PartialOrd};
 use std::convert::From;
 use std::convert::TryFrom;
+use std::iter::Sum;
+use std::ops::Add;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Offset(pub usize);
@@ -23,6 +26,9 @@ pub struct Key(pub String);
 pub struct KeyValuePair {
     pub key: String,
     pub value: String,
+    /// Time (in seconds since the Unix epoch) from which the pair is treated as deleted,
+    /// None if it never expires.
+    pub expires_at: Option<u64>,
 }
 
 impl Ord for KeyValuePair {
@@ -39,21 +45,115 @@ impl PartialOrd for KeyValuePair {
 
 impl PartialEq for KeyValuePair {
     fn eq(&self, other: &Self) -> bool {
-        self.key == other.key && self.value == other.value
+        self.key == other.key && self.value == other.value && self.expires_at == other.expires_at
     }
 }
 
 impl KeyValuePair {
     pub fn new(key: String, value: String) -> KeyValuePair {
-        KeyValuePair { key, value }
+        KeyValuePair {
+            key,
+            value,
+            expires_at: None,
+        }
+    }
+
+    /// with_expiry returns a pair which expires at the given time, in seconds since the Unix epoch.
+    pub fn with_expiry(key: String, value: String, expires_at: u64) -> KeyValuePair {
+        KeyValuePair {
+            key,
+            value,
+            expires_at: Some(expires_at),
+        }
+    }
+
+    /// is_expired returns true if the pair expired at or before the given time.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// drop_expired removes the pairs which expired by now from the pairs of a leaf being rewritten.
+pub(crate) fn drop_expired(pairs: &mut Vec<KeyValuePair>) {
+    let now = now();
+    pairs.retain(|kv| !kv.is_expired(now));
+}
+
+/// now returns the current time in seconds since the Unix epoch, which pairs expire by.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Count sums up the key-value pairs in the sub tree of a child of an internal node.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Count {
+    /// Number of pairs, including the expired pairs no write dropped yet.
+    pub pairs: usize,
+    /// No pair of the sub tree expires before this time (u64::MAX if none of them expires),
+    /// which tells whether the sub tree may hold expired pairs without reading it.
+    pub earliest_expiry: u64,
+}
+
+impl Count {
+    pub fn new(pairs: usize, earliest_expiry: u64) -> Count {
+        Count {
+            pairs,
+            earliest_expiry,
+        }
+    }
+
+    /// of returns the count of the pairs of a leaf.
+    pub fn of(pairs: &[KeyValuePair]) -> Count {
+        Count {
+            pairs: pairs.len(),
+            earliest_expiry: pairs
+                .iter()
+                .filter_map(|kv| kv.expires_at)
+                .min()
+                .unwrap_or(u64::MAX),
+        }
+    }
+
+    /// is_live returns true if none of the pairs counted expired by the given time.
+    pub fn is_live(&self, now: u64) -> bool {
+        self.earliest_expiry > now
+    }
+}
+
+impl Default for Count {
+    fn default() -> Self {
+        Count {
+            pairs: 0,
+            earliest_expiry: u64::MAX,
+        }
+    }
+}
+
+impl Add for Count {
+    type Output = Count;
+
+    fn add(self, other: Count) -> Count {
+        Count {
+            pairs: self.pairs + other.pairs,
+            earliest_expiry: self.earliest_expiry.min(other.earliest_expiry),
+        }
+    }
+}
+
+impl Sum for Count {
+    fn sum<I: Iterator<Item = Count>>(iter: I) -> Count {
+        iter.fold(Count::default(), Add::add)
     }
 }
 
 // NodeType Represents different node types in the BTree.
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum NodeType {
-    /// Internal nodes contain a vector of pointers to their children and a vector of keys.
-    Internal(Vec<Offset>, Vec<Key>),
+    /// Internal nodes contain a vector of pointers to their children, a vector of keys
+    /// and the count of the key-value pairs in the sub tree of each child.
+    Internal(Vec<Offset>, Vec<Key>, Vec<Count>),
 
     /// Leaf nodes contain a vector of Keys and values.
     Leaf(Vec<KeyValuePair>),
@@ -61,11 +161,24 @@ pub enum NodeType {
     Unexpected,
 }
 
+impl NodeType {
+    /// count returns the count of the key-value pairs in the sub tree rooted at the node.
+    pub fn count(&self) -> Count {
+        match self {
+            NodeType::Internal(_, _, counts) => counts.iter().copied().sum(),
+            NodeType::Leaf(pairs) => Count::of(pairs),
+            NodeType::Unexpected => Count::default(),
+        }
+    }
+}
+
 // Converts a byte to a NodeType.
 impl From<u8> for NodeType {
     fn from(orig: u8) -> NodeType {
         match orig {
-            0x01 => NodeType::Internal(Vec::<Offset>::new(), Vec::<Key>::new()),
+            0x01 => {
+                NodeType::Internal(Vec::<Offset>::new(), Vec::<Key>::new(), Vec::<Count>::new())
+            }
             0x02 => NodeType::Leaf(Vec::<KeyValuePair>::new()),
             _ => NodeType::Unexpected,
         }
@@ -76,7 +189,7 @@ impl From<u8> for NodeType {
 impl From<&NodeType> for u8 {
     fn from(orig: &NodeType) -> u8 {
         match orig {
-            NodeType::Internal(_, _) => 0x01,
+            NodeType::Internal(_, _, _) => 0x01,
             NodeType::Leaf(_) => 0x02,
             NodeType::Unexpected => 0x03,
         }
@@ -0,0 +1,243 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{now, Count, Key, KeyValuePair, NodeType, Offset};
+use std::ops::{Bound, RangeBounds};
+
+impl BTree {
+    /// len returns the number of key-value pairs in the tree, leaving out expired pairs.
+    pub fn len(&mut self) -> Result<usize, Error> {
+        let root_offset = self.wal.get_root()?;
+        let count = self.pager.get_node(&root_offset)?.node_type.count();
+        self.live(&root_offset, count, now())
+    }
+
+    /// is_empty returns true if the tree holds no key-value pairs.
+    pub fn is_empty(&mut self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// rank returns the number of keys in the tree smaller than the given key.
+    pub fn rank(&mut self, key: &str) -> Result<usize, Error> {
+        self.position(key, false)
+    }
+
+    /// count_range returns the number of keys within the given range.
+    pub fn count_range<R: RangeBounds<String>>(&mut self, range: R) -> Result<usize, Error> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => self.position(start, false)?,
+            Bound::Excluded(start) => self.position(start, true)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => self.position(end, true)?,
+            Bound::Excluded(end) => self.position(end, false)?,
+            Bound::Unbounded => self.len()?,
+        };
+        Ok(end.saturating_sub(start))
+    }
+
+    /// select returns the key-value pair at the given index of the tree in key order (counting from zero),
+    /// or KeyNotFound if the tree holds no more pairs than the index.
+    pub fn select(&mut self, idx: usize) -> Result<KeyValuePair, Error> {
+        let now = now();
+        let mut idx = idx;
+        let mut offset = self.wal.get_root()?;
+        loop {
+            match self.pager.get_node(&offset)?.node_type {
+                NodeType::Internal(children, _, counts) => {
+                    // Skip the sub trees of the children preceding the one holding the pair.
+                    let mut child = None;
+                    for (child_offset, count) in children.into_iter().zip(counts) {
+                        let live = self.live(&child_offset, count, now)?;
+                        if idx < live {
+                            child = Some(child_offset);
+                            break;
+                        }
+                        idx -= live;
+                    }
+                    offset = child.ok_or(Error::KeyNotFound)?;
+                }
+                NodeType::Leaf(pairs) => {
+                    return pairs
+                        .into_iter()
+                        .filter(|kv| !kv.is_expired(now))
+                        .nth(idx)
+                        .ok_or(Error::KeyNotFound)
+                }
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+        }
+    }
+
+    /// position returns the number of keys smaller than the given key (or equal to it if inclusive)
+    /// by adding up the counts of the sub trees left of the path to the key.
+    fn position(&mut self, key: &str, inclusive: bool) -> Result<usize, Error> {
+        let before = |other: &str| other < key || (inclusive && other == key);
+        let now = now();
+        let mut res = 0;
+        let mut offset = self.wal.get_root()?;
+        loop {
+            match self.pager.get_node(&offset)?.node_type {
+                NodeType::Internal(children, keys, counts) => {
+                    // The children preceding idx only hold keys up to their separator.
+                    let idx = keys.partition_point(|Key(other)| before(other));
+                    for (child_offset, count) in children.iter().zip(counts).take(idx) {
+                        res += self.live(child_offset, count, now)?;
+                    }
+                    offset = children.get(idx).ok_or(Error::UnexpectedError)?.clone();
+                }
+                NodeType::Leaf(pairs) => {
+                    let end = pairs.partition_point(|kv| before(&kv.key));
+                    return Ok(res + pairs[..end].iter().filter(|kv| !kv.is_expired(now)).count());
+                }
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+        }
+    }
+
+    /// live returns the number of pairs of the sub tree rooted at the given offset (counted by count)
+    /// which did not expire by now. Only the sub trees which may hold expired pairs are read.
+    pub(crate) fn live(&mut self, offset: &Offset, count: Count, now: u64) -> Result<usize, Error> {
+        if count.is_live(now) {
+            return Ok(count.pairs);
+        }
+        match self.pager.get_node(offset)?.node_type {
+            NodeType::Internal(children, _, counts) => {
+                let mut res = 0;
+                for (child_offset, count) in children.iter().zip(counts) {
+                    res += self.live(child_offset, count, now)?;
+                }
+                Ok(res)
+            }
+            NodeType::Leaf(pairs) => Ok(pairs.iter().filter(|kv| !kv.is_expired(now)).count()),
+            NodeType::Unexpected => Err(Error::UnexpectedError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn order_statistics_work() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{Key, KeyValuePair};
+        use crate::storage::MemoryStorage;
+        use std::ops::Bound;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        assert!(btree.is_empty()?);
+        assert_eq!(btree.rank("a")?, 0);
+        assert!(matches!(btree.select(0), Err(Error::KeyNotFound)));
+
+        // Even keys, with every fourth one deleted again.
+        let mut keys: Vec<String> = (0..80).map(|i| format!("{:03}", i * 2)).collect();
+        for key in &keys {
+            btree.insert(KeyValuePair::new(key.clone(), key.clone()))?;
+        }
+        for key in keys.iter().step_by(2) {
+            btree.delete(Key(key.clone()))?;
+        }
+        keys = keys.into_iter().skip(1).step_by(2).collect();
+        assert_eq!(btree.len()?, keys.len());
+        assert!(btree.check()?.is_empty());
+
+        for (idx, key) in keys.iter().enumerate() {
+            assert_eq!(btree.select(idx)?.key, *key);
+            assert_eq!(btree.rank(key)?, idx);
+        }
+        assert!(matches!(btree.select(keys.len()), Err(Error::KeyNotFound)));
+        // Keys between and around those in the tree.
+        assert_eq!(btree.rank("")?, 0);
+        assert_eq!(btree.rank("003")?, 1);
+        assert_eq!(btree.rank("9")?, keys.len());
+
+        let bound = |bound: Bound<&str>| bound.map(str::to_string);
+        for (start, end) in [
+            (Bound::Included("002"), Bound::Excluded("010")),
+            (Bound::Excluded("002"), Bound::Included("010")),
+            (Bound::Included("001"), Bound::Included("099")),
+            (Bound::Unbounded, Bound::Excluded("050")),
+            (Bound::Excluded("150"), Bound::Unbounded),
+            (Bound::Included("050"), Bound::Excluded("010")),
+        ] {
+            let expected = btree.scan((bound(start), bound(end)))?.len();
+            assert_eq!(btree.count_range((bound(start), bound(end)))?, expected);
+        }
+        assert_eq!(btree.count_range(..)?, keys.len());
+        Ok(())
+    }
+
+    #[test]
+    fn order_statistics_skip_expired_pairs() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(50);
+        for b in 2..5 {
+            let mut btree = BTreeBuilder::new()
+                .storage(MemoryStorage::new(), MemoryStorage::new())
+                .b_parameter(b)
+                .build()?;
+            // Runs of expired pairs fill whole sub trees, others are scattered between live pairs
+            // and pairs expiring at the latest time an expiry can hold.
+            let keys: Vec<String> = (0..300).map(|i| format!("{:03}", i)).collect();
+            let mut live = Vec::new();
+            for (i, key) in keys.iter().enumerate() {
+                let kv = match rng.gen_range(0..3) {
+                    _ if (i / 40) % 3 == 1 => {
+                        KeyValuePair::with_expiry(key.clone(), key.clone(), 1)
+                    }
+                    0 => KeyValuePair::with_expiry(key.clone(), key.clone(), 1),
+                    1 => KeyValuePair::with_expiry(key.clone(), key.clone(), u64::MAX),
+                    _ => KeyValuePair::new(key.clone(), key.clone()),
+                };
+                if kv.expires_at != Some(1) {
+                    live.push(key.clone());
+                }
+                btree.insert(kv)?;
+            }
+            assert!(btree.check()?.is_empty());
+
+            assert_eq!(btree.len()?, live.len());
+            for (idx, key) in live.iter().enumerate() {
+                assert_eq!(btree.select(idx)?.key, *key);
+            }
+            assert!(matches!(btree.select(live.len()), Err(Error::KeyNotFound)));
+            for key in &keys {
+                let smaller = live.partition_point(|other| other < key);
+                assert_eq!(btree.rank(key)?, smaller, "b {} key {}", b, key);
+            }
+            for _ in 0..50 {
+                let start = keys[rng.gen_range(0..keys.len())].clone();
+                let end = keys[rng.gen_range(0..keys.len())].clone();
+                let expected = live
+                    .iter()
+                    .filter(|key| **key >= start && **key <= end)
+                    .count();
+                assert_eq!(btree.count_range(start..=end)?, expected);
+            }
+        }
+
+        // A tree holding expired pairs only is empty.
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for i in 0..20 {
+            let key = format!("{:02}", i);
+            btree.insert(KeyValuePair::with_expiry(key.clone(), key, 1))?;
+        }
+        assert!(btree.is_empty()?);
+        assert_eq!(btree.rank("10")?, 0);
+        assert!(matches!(btree.select(0), Err(Error::KeyNotFound)));
+        Ok(())
+    }
+}
@@ -2,10 +2,10 @@ use crate::error::Error;
 use crate::node::Node;
 use crate::node_type::{Key, NodeType, Offset};
 use crate::page_layout::{
-    ToByte, INTERNAL_NODE_HEADER_SIZE, INTERNAL_NODE_NUM_CHILDREN_OFFSET,
-    INTERNAL_NODE_NUM_CHILDREN_SIZE, IS_ROOT_OFFSET, KEY_SIZE, LEAF_NODE_HEADER_SIZE,
-    LEAF_NODE_NUM_PAIRS_OFFSET, LEAF_NODE_NUM_PAIRS_SIZE, NODE_TYPE_OFFSET, PAGE_SIZE,
-    PARENT_POINTER_OFFSET, PARENT_POINTER_SIZE, PTR_SIZE, VALUE_SIZE,
+    ToByte, CHECKSUM_OFFSET, CHECKSUM_SIZE, EXPIRY_SIZE, HAS_EXPIRY_SIZE,
+    INTERNAL_NODE_HEADER_SIZE, INTERNAL_NODE_NUM_CHILDREN_OFFSET, INTERNAL_NODE_NUM_CHILDREN_SIZE,
+    IS_ROOT_OFFSET, KEY_SIZE, LEAF_NODE_HEADER_SIZE, LEAF_NODE_NUM_PAIRS_OFFSET,
+    LEAF_NODE_NUM_PAIRS_SIZE, NODE_TYPE_OFFSET, PAGE_SIZE, PTR_SIZE, VALUE_SIZE,
 };
 use std::convert::TryFrom;
 
@@ -84,6 +84,27 @@ impl Page {
     pub fn get_data(&self) -> [u8; PAGE_SIZE] {
         *self.data
     }
+
+    /// checksum computes the CRC32C of the page as if its checksum field was zeroed.
+    pub fn checksum(&self) -> u32 {
+        let crc = crc32c::crc32c(&self.data[..CHECKSUM_OFFSET]);
+        let crc = crc32c::crc32c_append(crc, &[0x00; CHECKSUM_SIZE]);
+        crc32c::crc32c_append(crc, &self.data[CHECKSUM_OFFSET + CHECKSUM_SIZE..])
+    }
+
+    /// seal stores the checksum of the page in its header.
+    pub fn seal(&mut self) {
+        let checksum = self.checksum();
+        self.data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE]
+            .clone_from_slice(&checksum.to_be_bytes());
+    }
+
+    /// is_intact returns true if the checksum stored in the header matches the page.
+    pub fn is_intact(&self) -> bool {
+        let mut stored = [0x00; CHECKSUM_SIZE];
+        stored.clone_from_slice(&self.data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + CHECKSUM_SIZE]);
+        u32::from_be_bytes(stored) == self.checksum()
+    }
 }
 
 /// Implement TryFrom<Box<Node>> for Page allowing for easier
@@ -98,19 +119,8 @@ impl TryFrom<&Node> for Page {
         // node_type byte
         data[NODE_TYPE_OFFSET] = u8::from(&node.node_type);
 
-        // parent offest
-        if !node.is_root {
-            match node.parent_offset {
-                Some(Offset(parent_offset)) => data
-                    [PARENT_POINTER_OFFSET..PARENT_POINTER_OFFSET + PARENT_POINTER_SIZE]
-                    .clone_from_slice(&parent_offset.to_be_bytes()),
-                // Expected an offset of an inner / leaf node.
-                None => return Err(Error::UnexpectedError),
-            };
-        }
-
         match &node.node_type {
-            NodeType::Internal(child_offsets, keys) => {
+            NodeType::Internal(child_offsets, keys, counts) => {
                 data[INTERNAL_NODE_NUM_CHILDREN_OFFSET
                     ..INTERNAL_NODE_NUM_CHILDREN_OFFSET + INTERNAL_NODE_NUM_CHILDREN_SIZE]
                     .clone_from_slice(&child_offsets.len().to_be_bytes());
@@ -122,6 +132,21 @@ impl TryFrom<&Node> for Page {
                     page_offset += PTR_SIZE;
                 }
 
+                // The number of pairs in the sub tree of each child, followed by their earliest expiries.
+                if counts.len() != child_offsets.len() {
+                    return Err(Error::UnexpectedError);
+                }
+                for count in counts {
+                    data[page_offset..page_offset + PTR_SIZE]
+                        .clone_from_slice(&count.pairs.to_be_bytes());
+                    page_offset += PTR_SIZE;
+                }
+                for count in counts {
+                    data[page_offset..page_offset + EXPIRY_SIZE]
+                        .clone_from_slice(&count.earliest_expiry.to_be_bytes());
+                    page_offset += EXPIRY_SIZE;
+                }
+
                 for Key(key) in keys {
                     let key_bytes = key.as_bytes();
                     let mut raw_key: [u8; KEY_SIZE] = [0x00; KEY_SIZE];
@@ -167,6 +192,13 @@ impl TryFrom<&Node> for Page {
                     }
                     data[page_offset..page_offset + VALUE_SIZE].clone_from_slice(&raw_value);
                     page_offset += VALUE_SIZE;
+
+                    data[page_offset] = pair.expires_at.is_some().to_byte();
+                    page_offset += HAS_EXPIRY_SIZE;
+                    let expires_at = pair.expires_at.unwrap_or_default();
+                    data[page_offset..page_offset + EXPIRY_SIZE]
+                        .clone_from_slice(&expires_at.to_be_bytes());
+                    page_offset += EXPIRY_SIZE;
                 }
             }
             NodeType::Unexpected => return Err(Error::UnexpectedError),
@@ -195,6 +227,7 @@ impl TryFrom<&[u8]> for Value {
     }
 }
 
+#[cfg(test)]
 mod tests {
     use crate::error::Error;
 
@@ -212,7 +245,6 @@ mod tests {
                 KeyValuePair::new("ariana".to_string(), "grande".to_string()),
             ]),
             true,
-            None,
         );
 
         // Serialize data.
@@ -222,14 +254,68 @@ mod tests {
 
         assert_eq!(res.is_root, some_leaf.is_root);
         assert_eq!(res.node_type, some_leaf.node_type);
-        assert_eq!(res.parent_offset, some_leaf.parent_offset);
+        Ok(())
+    }
+
+    #[test]
+    fn node_to_page_keeps_expiries() -> Result<(), Error> {
+        use crate::node::Node;
+        use crate::node_type::{KeyValuePair, NodeType};
+        use crate::page::Page;
+        use std::convert::TryFrom;
+
+        // Neither zero nor the highest expiry is mistaken for a pair which never expires.
+        let leaf = Node::new(
+            NodeType::Leaf(vec![
+                KeyValuePair::new("a".to_string(), "never".to_string()),
+                KeyValuePair::with_expiry("b".to_string(), "epoch".to_string(), 0),
+                KeyValuePair::with_expiry("c".to_string(), "last".to_string(), u64::MAX),
+            ]),
+            false,
+        );
+        let res = Node::try_from(Page::try_from(&leaf)?)?;
+        assert_eq!(res.node_type, leaf.node_type);
+        if let NodeType::Leaf(pairs) = res.node_type {
+            let expiries: Vec<Option<u64>> = pairs.iter().map(|kv| kv.expires_at).collect();
+            assert_eq!(expiries, vec![None, Some(0), Some(u64::MAX)]);
+            assert!(pairs[1].is_expired(0));
+            assert!(!pairs[2].is_expired(u64::MAX - 1));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn page_checksum_works() -> Result<(), Error> {
+        use crate::node::Node;
+        use crate::node_type::{KeyValuePair, NodeType};
+        use crate::page::Page;
+        use crate::page_layout::{LEAF_NODE_NUM_PAIRS_OFFSET, PTR_SIZE};
+        use std::convert::TryFrom;
+
+        let some_leaf = Node::new(
+            NodeType::Leaf(vec![KeyValuePair::new(
+                "foo".to_string(),
+                "bar".to_string(),
+            )]),
+            true,
+        );
+
+        let mut page = Page::try_from(&some_leaf)?;
+        assert!(!page.is_intact());
+        page.seal();
+        assert!(page.is_intact());
+
+        // Flip a single bit in the number of pairs.
+        let mut data = page.get_data();
+        data[LEAF_NODE_NUM_PAIRS_OFFSET + PTR_SIZE - 1] ^= 0x01;
+        assert!(!Page::new(data).is_intact());
         Ok(())
     }
 
     #[test]
     fn node_to_page_works_for_internal_node() -> Result<(), Error> {
         use crate::node::Node;
-        use crate::node_type::{Key, NodeType, Offset};
+        use crate::node_type::{Count, Key, NodeType, Offset};
         use crate::page::Page;
         use crate::page_layout::PAGE_SIZE;
         use std::convert::TryFrom;
@@ -247,9 +333,14 @@ mod tests {
                     Key("lebron".to_string()),
                     Key("ariana".to_string()),
                 ],
+                vec![
+                    Count::new(1, u64::MAX),
+                    Count::new(2, 86400),
+                    Count::new(3, 0),
+                    Count::new(4, u64::MAX),
+                ],
             ),
             true,
-            None,
         );
 
         // Serialize data.
@@ -259,7 +350,6 @@ mod tests {
 
         assert_eq!(res.is_root, internal_node.is_root);
         assert_eq!(res.node_type, internal_node.node_type);
-        assert_eq!(res.parent_offset, internal_node.parent_offset);
         Ok(())
     }
 }
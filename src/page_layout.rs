@@ -7,50 +7,65 @@ pub const PAGE_SIZE: usize = 4096;
 
 pub const PTR_SIZE: usize = size_of::<usize>();
 
-/// Common Node header layout (Ten bytes in total)
+/// Common Node header layout (Six bytes in total)
+///
+/// Nodes do not point at their parent: under copy-on-write the parent of an unchanged node
+/// moves on every write, instead operations keep the path they descended along in memory.
 pub const IS_ROOT_SIZE: usize = 1;
 pub const IS_ROOT_OFFSET: usize = 0;
 pub const NODE_TYPE_SIZE: usize = 1;
 pub const NODE_TYPE_OFFSET: usize = 1;
-pub const PARENT_POINTER_OFFSET: usize = 2;
-pub const PARENT_POINTER_SIZE: usize = PTR_SIZE;
-pub const COMMON_NODE_HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_POINTER_SIZE;
+/// CRC32C of the whole page, computed while the checksum itself is zeroed.
+pub const CHECKSUM_OFFSET: usize = 2;
+pub const CHECKSUM_SIZE: usize = 4;
+pub const COMMON_NODE_HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + CHECKSUM_SIZE;
 
-/// Leaf node header layout (Eighteen bytes in total)
+/// Leaf node header layout (Fourteen bytes in total)
 ///
-/// Space for keys and values: PAGE_SIZE - LEAF_NODE_HEADER_SIZE = 4096 - 18 = 4078 bytes.
-/// Which leaves 4076 / keys_limit = 20 (ten for key and 10 for value).
+/// Space for keys and values: PAGE_SIZE - LEAF_NODE_HEADER_SIZE = 4096 - 14 = 4082 bytes.
+/// Each pair takes 29 bytes (ten for the key, 10 for the value, 1 telling whether it expires and 8 for its expiry),
+/// which fits 4082 / 29 = 140 pairs, more than the 2*b-1 = 119 a leaf holds at most.
 pub const LEAF_NODE_NUM_PAIRS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
 pub const LEAF_NODE_NUM_PAIRS_SIZE: usize = PTR_SIZE;
 pub const LEAF_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE + LEAF_NODE_NUM_PAIRS_SIZE;
 
-/// Internal header layout (Eighteen bytes in total)
+/// Internal header layout (Fourteen bytes in total)
 ///
-// Space for children and keys: PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE = 4096 - 18 = 4078 bytes.
+// Space for children and keys: PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE = 4096 - 14 = 4082 bytes.
 pub const INTERNAL_NODE_NUM_CHILDREN_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
 pub const INTERNAL_NODE_NUM_CHILDREN_SIZE: usize = PTR_SIZE;
 pub const INTERNAL_NODE_HEADER_SIZE: usize =
     COMMON_NODE_HEADER_SIZE + INTERNAL_NODE_NUM_CHILDREN_SIZE;
 
-/// On a 64 bit machine the maximum space to keep all of the pointer
-/// is 200 * 8 = 1600 bytes.
+/// On a 64 bit machine the maximum space to keep all of the pointers
+/// is 120 * 8 = 960 bytes.
 #[allow(dead_code)]
 pub const MAX_SPACE_FOR_CHILDREN: usize = MAX_BRANCHING_FACTOR * PTR_SIZE;
 
-/// This leaves the keys of an internal node 2478 bytes:
-/// We use 1990 bytes for keys which leaves 488 bytes as junk.
-/// This means each key is limited to 12 bytes. (2476 / keys limit = ~12)
-/// Rounded down to 10 to accomodate the leave node.
+/// The number of pairs in the sub tree of each child takes as much space again (960 bytes),
+/// and so does the earliest expiry of the pairs in each sub tree (960 bytes).
+#[allow(dead_code)]
+pub const MAX_SPACE_FOR_COUNTS: usize = MAX_BRANCHING_FACTOR * (PTR_SIZE + EXPIRY_SIZE);
+
+/// This leaves the keys of an internal node 1202 bytes:
+/// We use 1190 bytes for keys which leaves 12 bytes as junk.
+/// This means each key is limited to 10 bytes. (1202 / keys limit = ~10)
 #[allow(dead_code)]
 pub const MAX_SPACE_FOR_KEYS: usize =
-    PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE - MAX_SPACE_FOR_CHILDREN;
+    PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE - MAX_SPACE_FOR_CHILDREN - MAX_SPACE_FOR_COUNTS;
 
 /// Key, Value sizes.
 pub const KEY_SIZE: usize = 10;
 pub const VALUE_SIZE: usize = 10;
+/// Whether a pair expires (one byte), followed by the time it expires at
+/// in seconds since the Unix epoch (big endian), zeroed if it never expires.
+pub const HAS_EXPIRY_SIZE: usize = 1;
+pub const EXPIRY_SIZE: usize = 8;
+pub const PAIR_SIZE: usize = KEY_SIZE + VALUE_SIZE + HAS_EXPIRY_SIZE + EXPIRY_SIZE;
 
 /// Wrappers for converting byte to bool and back.
 /// The convention used throughout the index file is: one is true; otherwise - false.
+#[allow(clippy::wrong_self_convention)]
 pub trait FromByte {
     fn from_byte(&self) -> bool;
 }
@@ -1,50 +1,122 @@
 use crate::error::Error;
+use crate::node::Node;
 use crate::node_type::Offset;
 use crate::page::Page;
-use crate::page_layout::PAGE_SIZE;
-use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::io::{Read, Seek, SeekFrom};
-use std::path::Path;
+use crate::storage::PageStorage;
+use std::convert::TryFrom;
 
 pub struct Pager {
-    file: File,
-    curser: usize,
+    storage: Box<dyn PageStorage>,
 }
 
 impl Pager {
-    pub fn new(path: &Path) -> Result<Pager, Error> {
-        let fd = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .open(path)?;
-
-        Ok(Pager {
-            file: fd,
-            curser: 0,
-        })
+    pub fn new(storage: Box<dyn PageStorage>) -> Pager {
+        Pager { storage }
     }
 
+    /// get_page reads the page at a given offset verifying its checksum.
     pub fn get_page(&mut self, offset: &Offset) -> Result<Page, Error> {
-        let mut page: [u8; PAGE_SIZE] = [0x00; PAGE_SIZE];
-        self.file.seek(SeekFrom::Start(offset.0 as u64))?;
-        self.file.read_exact(&mut page)?;
-        Ok(Page::new(page))
+        let page = self.storage.read_page(offset)?;
+        if !page.is_intact() {
+            return Err(Error::Corruption { offset: offset.0 });
+        }
+        Ok(page)
+    }
+
+    /// get_node reads and decodes the node at a given offset,
+    /// reporting a page which does not hold a valid node as corrupt at that offset.
+    pub fn get_node(&mut self, offset: &Offset) -> Result<Node, Error> {
+        Node::try_from(self.get_page(offset)?).map_err(|err| match err {
+            Error::Corruption { .. } => Error::Corruption { offset: offset.0 },
+            err => err,
+        })
     }
 
     pub fn write_page(&mut self, page: Page) -> Result<Offset, Error> {
-        self.file.seek(SeekFrom::Start(self.curser as u64))?;
-        self.file.write_all(&page.get_data())?;
-        let res = Offset(self.curser);
-        self.curser += PAGE_SIZE;
+        let res = self.storage.allocate_page()?;
+        self.write_page_at_offset(page, &res)?;
         Ok(res)
     }
 
-    pub fn write_page_at_offset(&mut self, page: Page, offset: &Offset) -> Result<(), Error> {
-        self.file.seek(SeekFrom::Start(offset.0 as u64))?;
-        self.file.write_all(&page.get_data())?;
+    pub fn write_page_at_offset(&mut self, mut page: Page, offset: &Offset) -> Result<(), Error> {
+        page.seal();
+        self.storage.write_page(&page, offset)
+    }
+
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.storage.sync()
+    }
+
+    /// size returns the size of the underlying storage in bytes.
+    pub fn size(&mut self) -> Result<usize, Error> {
+        self.storage.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn get_page_detects_corruption() -> Result<(), Error> {
+        use crate::node::Node;
+        use crate::node_type::{KeyValuePair, NodeType};
+        use crate::page::Page;
+        use crate::page_layout::LEAF_NODE_HEADER_SIZE;
+        use crate::pager::Pager;
+        use crate::storage::MemoryStorage;
+        use std::convert::TryFrom;
+
+        let mut pager = Pager::new(Box::new(MemoryStorage::new()));
+        let leaf = Node::new(
+            NodeType::Leaf(vec![KeyValuePair::new(
+                "foo".to_string(),
+                "bar".to_string(),
+            )]),
+            true,
+        );
+        let offset = pager.write_page(Page::try_from(&leaf)?)?;
+        let page = pager.get_page(&offset)?;
+        assert_eq!(Node::try_from(page)?.node_type, leaf.node_type);
+
+        // Flip a bit of the key behind the pager's back.
+        let mut data = pager.storage.read_page(&offset)?.get_data();
+        data[LEAF_NODE_HEADER_SIZE] ^= 0x01;
+        pager.storage.write_page(&Page::new(data), &offset)?;
+        let res = pager.get_page(&offset);
+        assert!(matches!(res, Err(Error::Corruption { offset: 0 })));
+        Ok(())
+    }
+
+    #[test]
+    fn get_node_detects_oversized_counts() -> Result<(), Error> {
+        use crate::node::Node;
+        use crate::node_type::{KeyValuePair, NodeType};
+        use crate::page::Page;
+        use crate::page_layout::LEAF_NODE_NUM_PAIRS_OFFSET;
+        use crate::pager::Pager;
+        use crate::storage::MemoryStorage;
+        use std::convert::TryFrom;
+
+        let mut pager = Pager::new(Box::new(MemoryStorage::new()));
+        let leaf = Node::new(
+            NodeType::Leaf(vec![KeyValuePair::new(
+                "foo".to_string(),
+                "bar".to_string(),
+            )]),
+            true,
+        );
+        pager.write_page(Page::try_from(&leaf)?)?;
+        let offset = pager.write_page(Page::try_from(&leaf)?)?;
+        assert_eq!(pager.get_node(&offset)?.node_type, leaf.node_type);
+
+        // A page with a valid checksum whose pair count does not fit in it.
+        let mut page = pager.get_page(&offset)?;
+        page.write_value_at_offset(LEAF_NODE_NUM_PAIRS_OFFSET, usize::MAX)?;
+        pager.write_page_at_offset(page, &offset)?;
+        let res = pager.get_node(&offset);
+        assert!(matches!(res, Err(Error::Corruption { offset: o }) if o == offset.0));
+        assert_ne!(offset.0, 0);
         Ok(())
     }
 }
@@ -0,0 +1,108 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::iter::Range;
+use crate::node_type::KeyValuePair;
+use std::ops::Bound;
+
+impl BTree {
+    /// scan_prefix returns every key-value pair whose key starts with the given prefix, in key order.
+    pub fn scan_prefix(&mut self, prefix: &str) -> Result<Vec<KeyValuePair>, Error> {
+        self.range_prefix(prefix)?.collect()
+    }
+
+    /// count_prefix returns the number of keys starting with the given prefix,
+    /// without reading the leaves between the first and last of them.
+    pub fn count_prefix(&mut self, prefix: &str) -> Result<usize, Error> {
+        self.count_range((Bound::Included(prefix.to_string()), prefix_end(prefix)))
+    }
+
+    /// delete_prefix deletes every key starting with the given prefix in a single pass,
+    /// returning the number of pairs deleted.
+    pub fn delete_prefix(&mut self, prefix: &str) -> Result<usize, Error> {
+        self.delete_range((Bound::Included(prefix.to_string()), prefix_end(prefix)))
+    }
+
+    /// range_prefix returns an iterator over the keys starting with the given prefix, which are
+    /// exactly the keys within [prefix, end) where end is the smallest string greater than every one of them.
+    fn range_prefix(&mut self, prefix: &str) -> Result<Range<'_>, Error> {
+        self.range((Bound::Included(prefix.to_string()), prefix_end(prefix)))
+    }
+}
+
+/// prefix_end returns the smallest string greater than every string starting with the given prefix,
+/// which is the prefix with its last character incremented (dropping characters which cannot be).
+fn prefix_end(prefix: &str) -> Bound<String> {
+    let mut end = prefix.to_string();
+    while let Some(last) = end.pop() {
+        // Skip over the surrogate code points, which are not valid characters.
+        let next = match last {
+            '\u{d7ff}' => Some('\u{e000}'),
+            _ => char::from_u32(last as u32 + 1),
+        };
+        if let Some(next) = next {
+            end.push(next);
+            return Bound::Excluded(end);
+        }
+    }
+    Bound::Unbounded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn prefix_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        let mut keys = Vec::new();
+        for tenant in ["a", "a0", "a/", "b"] {
+            for user in 0..5 {
+                keys.push(format!("{}/{}", tenant, user));
+            }
+        }
+        keys.push("a".to_string());
+        keys.push("a\u{10ffff}".to_string());
+        for key in &keys {
+            btree.insert(KeyValuePair::new(key.clone(), key.clone()))?;
+        }
+        let with_prefix = |prefix: &str| -> Vec<String> {
+            let mut res: Vec<String> = keys
+                .iter()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect();
+            res.sort();
+            res
+        };
+
+        for prefix in ["a/", "a", "a0/", "b/1", "c", "", "a\u{10ffff}"] {
+            let found: Vec<String> = btree
+                .scan_prefix(prefix)?
+                .into_iter()
+                .map(|kv| kv.key)
+                .collect();
+            assert_eq!(found, with_prefix(prefix), "prefix {:?}", prefix);
+            assert_eq!(btree.count_prefix(prefix)?, found.len());
+        }
+
+        // Expired pairs are neither counted nor reported as deleted.
+        for user in 5..8 {
+            let key = format!("a/{}", user);
+            btree.insert(KeyValuePair::with_expiry(key.clone(), key, 1))?;
+        }
+        assert_eq!(btree.count_prefix("a/")?, 10);
+        assert_eq!(btree.delete_prefix("a/")?, 10);
+        assert_eq!(btree.delete_prefix("a/")?, 0);
+        assert!(btree.check()?.is_empty());
+        assert_eq!(btree.count_prefix("")?, keys.len() - 10);
+        assert_eq!(btree.count_prefix("a0/")?, 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::{NodeType, Offset};
+use std::fmt::Debug;
+use std::io::{self, Write};
+
+/// NodeInfo describes a single node visited by BTree::walk.
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+    pub offset: Offset,
+    /// Depth of the node, the root is at depth zero.
+    pub depth: usize,
+    /// The node type holding its keys (and children or key-value pairs).
+    pub node_type: NodeType,
+}
+
+/// Visitor is called by BTree::walk for each node in the tree.
+pub trait Visitor {
+    /// visit is called for a node before any of its children,
+    /// returning false skips the children of the node.
+    fn visit(&mut self, node: &NodeInfo) -> Result<bool, Error>;
+}
+
+impl<F: FnMut(&NodeInfo) -> Result<bool, Error>> Visitor for F {
+    fn visit(&mut self, node: &NodeInfo) -> Result<bool, Error> {
+        self(node)
+    }
+}
+
+/// PrintOptions controls how much of the tree write_tree prints.
+#[derive(Clone, Debug, Default)]
+pub struct PrintOptions {
+    /// Nodes deeper than max_depth are not printed, the root is at depth zero.
+    pub max_depth: Option<usize>,
+    /// At most max_keys keys (or key-value pairs) are printed per node.
+    pub max_keys: Option<usize>,
+}
+
+impl BTree {
+    /// walk visits the nodes of the tree in pre-order (i.e. every node before its children)
+    /// starting from the current root.
+    pub fn walk<V: Visitor>(&mut self, visitor: &mut V) -> Result<(), Error> {
+        let root_offset = self.wal.get_root()?;
+        self.walk_sub_tree(root_offset, 0, visitor)
+    }
+
+    /// walk_sub_tree recursively visits the nodes rooted at a node given by its offset.
+    fn walk_sub_tree<V: Visitor>(
+        &mut self,
+        offset: Offset,
+        depth: usize,
+        visitor: &mut V,
+    ) -> Result<(), Error> {
+        let node = self.pager.get_node(&offset)?;
+        if let NodeType::Unexpected = node.node_type {
+            return Err(Error::UnexpectedError);
+        }
+        let info = NodeInfo {
+            offset,
+            depth,
+            node_type: node.node_type,
+        };
+        if !visitor.visit(&info)? {
+            return Ok(());
+        }
+        if let NodeType::Internal(children, _, _) = info.node_type {
+            for child_offset in children {
+                self.walk_sub_tree(child_offset, depth + 1, visitor)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// write_tree writes an indented description of the nodes of the tree to the given writer.
+    pub fn write_tree<W: Write>(
+        &mut self,
+        writer: &mut W,
+        options: &PrintOptions,
+    ) -> Result<(), Error> {
+        self.walk(&mut |node: &NodeInfo| -> Result<bool, Error> {
+            write_node(writer, node, options).map_err(Error::from)?;
+            Ok(options.max_depth.is_none_or(|max| node.depth < max))
+        })
+    }
+
+    /// print is a helper for recursively printing the tree.
+    pub fn print(&mut self) -> Result<(), Error> {
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        writeln!(handle)?;
+        self.write_tree(&mut handle, &PrintOptions::default())
+    }
+}
+
+/// write_node writes a single node indented according to its depth.
+fn write_node<W: Write>(writer: &mut W, node: &NodeInfo, options: &PrintOptions) -> io::Result<()> {
+    let prefix = "   |  ".repeat(node.depth);
+    writeln!(writer, "{}Node at offset: {}", prefix, node.offset.0)?;
+    match &node.node_type {
+        NodeType::Internal(children, keys, _) => {
+            writeln!(writer, "{}|->Keys: {}", prefix, truncated(keys, options))?;
+            writeln!(writer, "{}|->Children: {:?}", prefix, children)
+        }
+        NodeType::Leaf(pairs) => {
+            writeln!(
+                writer,
+                "{}|->Key value pairs: {}",
+                prefix,
+                truncated(pairs, options)
+            )
+        }
+        NodeType::Unexpected => Ok(()),
+    }
+}
+
+/// truncated formats a list of items keeping at most options.max_keys of them.
+fn truncated<T: Debug>(items: &[T], options: &PrintOptions) -> String {
+    match options.max_keys {
+        Some(max) if items.len() > max => {
+            let shown = format!("{:?}", &items[..max]);
+            format!(
+                "{} ... ({} more)",
+                shown.trim_end_matches(']'),
+                items.len() - max
+            )
+        }
+        _ => format!("{:?}", items),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn walk_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::{KeyValuePair, NodeType};
+        use crate::print::NodeInfo;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for i in 0..10 {
+            btree.insert(KeyValuePair::new(i.to_string(), i.to_string()))?;
+        }
+
+        let mut depths = Vec::new();
+        let mut leaf_keys = Vec::new();
+        btree.walk(&mut |node: &NodeInfo| -> Result<bool, Error> {
+            depths.push(node.depth);
+            if let NodeType::Leaf(pairs) = &node.node_type {
+                leaf_keys.extend(pairs.iter().map(|pair| pair.key.clone()));
+            }
+            Ok(true)
+        })?;
+        assert_eq!(depths[0], 0);
+        let expected: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        assert_eq!(leaf_keys, expected);
+
+        // Skipping the children of the root visits the root alone.
+        let mut visited = 0;
+        btree.walk(&mut |_: &NodeInfo| -> Result<bool, Error> {
+            visited += 1;
+            Ok(false)
+        })?;
+        assert_eq!(visited, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn write_tree_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::print::PrintOptions;
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for key in ["a", "b", "c", "d"] {
+            btree.insert(KeyValuePair::new(key.to_string(), key.to_string()))?;
+        }
+
+        let mut out = Vec::new();
+        btree.write_tree(&mut out, &PrintOptions::default())?;
+        let text = String::from_utf8(out).map_err(|_| Error::UTF8Error)?;
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1], "|->Keys: [Key(\"b\")]");
+        assert!(lines[4].starts_with("   |  |->Key value pairs: [KeyValuePair { key: \"a\""));
+
+        let mut out = Vec::new();
+        let options = PrintOptions {
+            max_depth: Some(0),
+            max_keys: None,
+        };
+        btree.write_tree(&mut out, &options)?;
+        assert_eq!(String::from_utf8_lossy(&out).lines().count(), 3);
+
+        let mut out = Vec::new();
+        let options = PrintOptions {
+            max_depth: None,
+            max_keys: Some(1),
+        };
+        btree.write_tree(&mut out, &options)?;
+        let text = String::from_utf8(out).map_err(|_| Error::UTF8Error)?;
+        assert!(text.contains("value: \"a\", expires_at: None } ... (1 more)"));
+        Ok(())
+    }
+
+    #[test]
+    fn write_tree_reports_write_errors() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::print::PrintOptions;
+        use crate::storage::MemoryStorage;
+        use std::io::{self, Write};
+
+        /// FailingWriter fails the first write of a leaf and accepts every other write,
+        /// so the nodes visited after the failing one are written successfully.
+        struct FailingWriter {
+            failed: bool,
+        }
+
+        impl Write for FailingWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.failed || !String::from_utf8_lossy(buf).contains("Key value pairs") {
+                    return Ok(buf.len());
+                }
+                self.failed = true;
+                Err(io::Error::other("disk full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        for key in ["a", "b", "c", "d"] {
+            btree.insert(KeyValuePair::new(key.to_string(), key.to_string()))?;
+        }
+
+        let mut writer = FailingWriter { failed: false };
+        assert!(btree
+            .write_tree(&mut writer, &PrintOptions::default())
+            .is_err());
+        Ok(())
+    }
+}
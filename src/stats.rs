@@ -0,0 +1,137 @@
+use crate::btree::BTree;
+use crate::error::Error;
+use crate::node_type::NodeType;
+use crate::page_layout::PAGE_SIZE;
+use crate::print::NodeInfo;
+
+/// Fill summarizes how full the nodes of a single type are,
+/// relative to the maximum of 2*b-1 keys a node can hold.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Fill {
+    pub min: f64,
+    pub max: f64,
+    pub average: f64,
+}
+
+impl Fill {
+    fn from_counts(counts: &[usize], capacity: usize) -> Fill {
+        if counts.is_empty() {
+            return Fill::default();
+        }
+        let capacity = capacity as f64;
+        let min = counts.iter().min().copied().unwrap_or_default() as f64;
+        let max = counts.iter().max().copied().unwrap_or_default() as f64;
+        let total: usize = counts.iter().sum();
+        Fill {
+            min: min / capacity,
+            max: max / capacity,
+            average: total as f64 / counts.len() as f64 / capacity,
+        }
+    }
+}
+
+/// Stats describes the shape of the current version of a tree and the space it occupies.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Stats {
+    /// Number of levels, a tree consisting of a single leaf has a height of one.
+    pub height: usize,
+    pub internal_nodes: usize,
+    pub leaf_nodes: usize,
+    /// Number of key-value pairs.
+    pub keys: usize,
+    pub internal_fill: Fill,
+    pub leaf_fill: Fill,
+    /// Size of the tree file in bytes.
+    pub file_size: usize,
+    /// Pages reachable from the current root.
+    pub reachable_pages: usize,
+    /// Pages belonging to previous versions of the tree (or never published).
+    pub unreachable_pages: usize,
+    /// Size of the write-ahead-log in bytes.
+    pub wal_size: usize,
+}
+
+/// Counts accumulates the number of keys per node over a traversal.
+#[derive(Default)]
+struct Counts {
+    height: usize,
+    internal: Vec<usize>,
+    leaf: Vec<usize>,
+}
+
+impl BTree {
+    /// stats traverses the tree from the root logged in the write-ahead-log
+    /// and reports its shape and space usage.
+    pub fn stats(&mut self) -> Result<Stats, Error> {
+        let mut counts = Counts::default();
+        self.walk(&mut |node: &NodeInfo| -> Result<bool, Error> {
+            match &node.node_type {
+                NodeType::Internal(_, keys, _) => counts.internal.push(keys.len()),
+                NodeType::Leaf(pairs) => {
+                    counts.leaf.push(pairs.len());
+                    counts.height = counts.height.max(node.depth + 1);
+                }
+                NodeType::Unexpected => return Err(Error::UnexpectedError),
+            }
+            Ok(true)
+        })?;
+
+        let capacity = 2 * self.b - 1;
+        let file_size = self.pager.size()?;
+        let reachable_pages = counts.internal.len() + counts.leaf.len();
+        Ok(Stats {
+            height: counts.height,
+            internal_nodes: counts.internal.len(),
+            leaf_nodes: counts.leaf.len(),
+            keys: counts.leaf.iter().sum(),
+            internal_fill: Fill::from_counts(&counts.internal, capacity),
+            leaf_fill: Fill::from_counts(&counts.leaf, capacity),
+            file_size,
+            reachable_pages,
+            unreachable_pages: (file_size / PAGE_SIZE).saturating_sub(reachable_pages),
+            wal_size: self.wal.size()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn stats_works() -> Result<(), Error> {
+        use crate::btree::BTreeBuilder;
+        use crate::node_type::KeyValuePair;
+        use crate::page_layout::{PAGE_SIZE, PTR_SIZE};
+        use crate::storage::MemoryStorage;
+
+        let mut btree = BTreeBuilder::new()
+            .storage(MemoryStorage::new(), MemoryStorage::new())
+            .b_parameter(2)
+            .build()?;
+        let stats = btree.stats()?;
+        assert_eq!(stats.height, 1);
+        assert_eq!(stats.leaf_nodes, 1);
+        assert_eq!(stats.keys, 0);
+        assert_eq!(stats.unreachable_pages, 0);
+
+        for key in ["a", "b", "c", "d"] {
+            btree.insert(KeyValuePair::new(key.to_string(), key.to_string()))?;
+        }
+        let stats = btree.stats()?;
+        assert_eq!(stats.height, 2);
+        assert_eq!(stats.internal_nodes, 1);
+        assert_eq!(stats.leaf_nodes, 2);
+        assert_eq!(stats.keys, 4);
+        assert_eq!(stats.internal_fill.max, 1.0 / 3.0);
+        assert_eq!(stats.leaf_fill.average, 2.0 / 3.0);
+        assert_eq!(stats.reachable_pages, 3);
+        assert_eq!(
+            stats.reachable_pages + stats.unreachable_pages,
+            stats.file_size / PAGE_SIZE
+        );
+        // The b parameter followed by the initial root and one root per insert.
+        assert_eq!(stats.wal_size, 6 * PTR_SIZE);
+        Ok(())
+    }
+}
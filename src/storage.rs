@@ -0,0 +1,263 @@
+use crate::error::Error;
+use crate::node_type::Offset;
+use crate::page::Page;
+use crate::page_layout::PAGE_SIZE;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// PageStorage is the medium the nodes of a BTree are persisted to.
+/// Pages are addressed by their offset which is always a multiple of PAGE_SIZE.
+pub trait PageStorage {
+    /// read_page reads the page stored at a given offset.
+    fn read_page(&mut self, offset: &Offset) -> Result<Page, Error>;
+
+    /// write_page writes a page at a given offset overriding its previous content.
+    fn write_page(&mut self, page: &Page, offset: &Offset) -> Result<(), Error>;
+
+    /// allocate_page reserves a new page at the end of the storage and returns its offset.
+    fn allocate_page(&mut self) -> Result<Offset, Error>;
+
+    /// sync makes sure every page written so far survives a crash.
+    fn sync(&mut self) -> Result<(), Error>;
+
+    /// size returns the size of the storage in bytes.
+    fn size(&mut self) -> Result<usize, Error>;
+}
+
+/// LogStorage is the append-only medium the write-ahead-log is persisted to.
+pub trait LogStorage {
+    /// append appends the given bytes to the end of the log.
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    /// read_at fills the buffer with the bytes of the log starting at a given offset.
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// len returns the length of the log in bytes.
+    fn len(&mut self) -> Result<usize, Error>;
+
+    /// is_empty returns true if nothing was appended to the log.
+    fn is_empty(&mut self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// truncate cuts the log to the given length in bytes.
+    fn truncate(&mut self, len: usize) -> Result<(), Error>;
+
+    /// sync makes sure every entry appended so far survives a crash.
+    fn sync(&mut self) -> Result<(), Error>;
+}
+
+/// FileStorage keeps pages or log entries in a file on disk.
+pub struct FileStorage {
+    file: File,
+    curser: usize,
+}
+
+impl FileStorage {
+    /// new opens (and truncates) the file at the given path, creating it if it does not exist.
+    pub fn new(path: &Path) -> Result<FileStorage, Error> {
+        let fd = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(FileStorage {
+            file: fd,
+            curser: 0,
+        })
+    }
+
+    /// open opens the file at the given path keeping its content, creating it if it does not exist.
+    pub fn open(path: &Path) -> Result<FileStorage, Error> {
+        let mut fd = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        let file_len = fd.seek(SeekFrom::End(0))? as usize;
+
+        Ok(FileStorage {
+            file: fd,
+            // Round up so that a trailing partial page is never handed out again.
+            curser: file_len.div_ceil(PAGE_SIZE) * PAGE_SIZE,
+        })
+    }
+}
+
+impl PageStorage for FileStorage {
+    fn read_page(&mut self, offset: &Offset) -> Result<Page, Error> {
+        let mut page: [u8; PAGE_SIZE] = [0x00; PAGE_SIZE];
+        self.file.seek(SeekFrom::Start(offset.0 as u64))?;
+        self.file.read_exact(&mut page)?;
+        Ok(Page::new(page))
+    }
+
+    fn write_page(&mut self, page: &Page, offset: &Offset) -> Result<(), Error> {
+        self.file.seek(SeekFrom::Start(offset.0 as u64))?;
+        self.file.write_all(&page.get_data())?;
+        Ok(())
+    }
+
+    fn allocate_page(&mut self) -> Result<Offset, Error> {
+        let res = Offset(self.curser);
+        self.curser += PAGE_SIZE;
+        Ok(res)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn size(&mut self) -> Result<usize, Error> {
+        Ok(self.file.metadata()?.len() as usize)
+    }
+}
+
+impl LogStorage for FileStorage {
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.file.read_exact(buf)?;
+        Ok(())
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        Ok(self.file.seek(SeekFrom::End(0))? as usize)
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error> {
+        self.file.set_len(len as u64)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// MemoryStorage keeps pages or log entries in memory,
+/// useful for tests and for ephemeral trees which need not survive the process.
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Vec<u8>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage { data: Vec::new() }
+    }
+}
+
+impl PageStorage for MemoryStorage {
+    fn read_page(&mut self, offset: &Offset) -> Result<Page, Error> {
+        let bytes = self
+            .data
+            .get(offset.0..offset.0 + PAGE_SIZE)
+            .ok_or(Error::UnexpectedError)?;
+        let mut page: [u8; PAGE_SIZE] = [0x00; PAGE_SIZE];
+        page.clone_from_slice(bytes);
+        Ok(Page::new(page))
+    }
+
+    fn write_page(&mut self, page: &Page, offset: &Offset) -> Result<(), Error> {
+        let bytes = self
+            .data
+            .get_mut(offset.0..offset.0 + PAGE_SIZE)
+            .ok_or(Error::UnexpectedError)?;
+        bytes.clone_from_slice(&page.get_data());
+        Ok(())
+    }
+
+    fn allocate_page(&mut self) -> Result<Offset, Error> {
+        let res = Offset(self.data.len());
+        self.data.resize(self.data.len() + PAGE_SIZE, 0x00);
+        Ok(res)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn size(&mut self) -> Result<usize, Error> {
+        Ok(self.data.len())
+    }
+}
+
+impl LogStorage for MemoryStorage {
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.data.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let bytes = self
+            .data
+            .get(offset..offset + buf.len())
+            .ok_or(Error::UnexpectedError)?;
+        buf.clone_from_slice(bytes);
+        Ok(())
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        Ok(self.data.len())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), Error> {
+        self.data.truncate(len);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    #[test]
+    fn memory_storage_pages_work() -> Result<(), Error> {
+        use crate::page::Page;
+        use crate::page_layout::PAGE_SIZE;
+        use crate::storage::{MemoryStorage, PageStorage};
+
+        let mut storage = MemoryStorage::new();
+        let first = storage.allocate_page()?;
+        let second = storage.allocate_page()?;
+        assert_eq!(first.0, 0);
+        assert_eq!(second.0, PAGE_SIZE);
+
+        storage.write_page(&Page::new([0x07; PAGE_SIZE]), &second)?;
+        assert_eq!(storage.read_page(&first)?.get_data()[0], 0x00);
+        assert_eq!(storage.read_page(&second)?.get_data()[0], 0x07);
+        Ok(())
+    }
+
+    #[test]
+    fn memory_storage_log_works() -> Result<(), Error> {
+        use crate::storage::{LogStorage, MemoryStorage};
+
+        let mut storage = MemoryStorage::new();
+        assert!(storage.is_empty()?);
+        storage.append(&[0x01, 0x02])?;
+        storage.append(&[0x03])?;
+        assert_eq!(storage.len()?, 3);
+
+        let mut buff = [0x00; 2];
+        storage.read_at(1, &mut buff)?;
+        assert_eq!(buff, [0x02, 0x03]);
+        Ok(())
+    }
+}
@@ -1,42 +1,87 @@
 use crate::error::Error;
 use crate::node_type::Offset;
 use crate::page_layout::PTR_SIZE;
+use crate::storage::LogStorage;
 use std::convert::TryFrom;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+
+/// The log starts with the b parameter of the tree, followed by the offset of every root logged.
+const HEADER_SIZE: usize = PTR_SIZE;
 
 pub struct Wal {
-    file: File,
+    storage: Box<dyn LogStorage>,
 }
 
 impl Wal {
-    pub fn new(parent_directoy: PathBuf) -> Result<Self, Error> {
-        let fd = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .open(parent_directoy.join("wal"))?;
+    pub fn new(mut storage: Box<dyn LogStorage>) -> Result<Self, Error> {
+        // A crash in the middle of set_root may leave a partially written entry behind,
+        // drop it so that the next entry is appended at a proper boundary.
+        let log_len = storage.len()?;
+        if log_len % PTR_SIZE != 0 {
+            storage.truncate(log_len - log_len % PTR_SIZE)?;
+        }
+        Ok(Self { storage })
+    }
+
+    /// get_b returns the b parameter of the tree, or None if the log is empty.
+    pub fn get_b(&mut self) -> Result<Option<usize>, Error> {
+        if self.storage.len()? < HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut buff: [u8; PTR_SIZE] = [0x00; PTR_SIZE];
+        self.storage.read_at(0, &mut buff)?;
+        Ok(Some(usize::from_be_bytes(buff)))
+    }
 
-        Ok(Self { file: fd })
+    /// set_b durably logs the b parameter of the tree, which must precede every root.
+    pub fn set_b(&mut self, b: usize) -> Result<(), Error> {
+        if !self.storage.is_empty()? {
+            return Err(Error::UnexpectedError);
+        }
+        self.storage.append(&b.to_be_bytes())?;
+        self.storage.sync()
+    }
+
+    /// has_root returns true if a root was ever logged.
+    pub fn has_root(&mut self) -> Result<bool, Error> {
+        Ok(self.storage.len()? > HEADER_SIZE)
     }
 
     pub fn get_root(&mut self) -> Result<Offset, Error> {
         let mut buff: [u8; PTR_SIZE] = [0x00; PTR_SIZE];
-        let file_len = self.file.seek(SeekFrom::End(0))? as usize;
-        let mut root_offset: usize = 0;
-        if file_len > 0 {
-            root_offset = (file_len / PTR_SIZE - 1) * PTR_SIZE;
+        let log_len = self.storage.len()?;
+        if log_len <= HEADER_SIZE {
+            return Err(Error::UnexpectedError);
         }
-        self.file.seek(SeekFrom::Start(root_offset as u64))?;
-        self.file.read_exact(&mut buff)?;
+        let root_offset = (log_len / PTR_SIZE - 1) * PTR_SIZE;
+        self.storage.read_at(root_offset, &mut buff)?;
         Offset::try_from(buff)
     }
 
+    /// get_roots returns every root ever logged, from the oldest to the current one.
+    pub fn get_roots(&mut self) -> Result<Vec<Offset>, Error> {
+        let log_len = self.storage.len()?;
+        if log_len <= HEADER_SIZE {
+            return Ok(Vec::new());
+        }
+        let mut buff = vec![0x00; log_len - HEADER_SIZE];
+        self.storage.read_at(HEADER_SIZE, &mut buff)?;
+        buff.chunks_exact(PTR_SIZE)
+            .map(|entry| {
+                let mut arr: [u8; PTR_SIZE] = [0x00; PTR_SIZE];
+                arr.clone_from_slice(entry);
+                Offset::try_from(arr)
+            })
+            .collect()
+    }
+
+    /// size returns the size of the log in bytes.
+    pub fn size(&mut self) -> Result<usize, Error> {
+        self.storage.len()
+    }
+
+    /// set_root durably logs the given offset as the current root.
     pub fn set_root(&mut self, offset: Offset) -> Result<(), Error> {
-        self.file.seek(SeekFrom::End(0))?;
-        self.file.write_all(&offset.0.to_be_bytes())?;
-        Ok(())
+        self.storage.append(&offset.0.to_be_bytes())?;
+        self.storage.sync()
     }
 }